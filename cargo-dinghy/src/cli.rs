@@ -1,4 +1,4 @@
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use std::collections::HashSet;
 
 #[derive(Parser, Debug, Clone)]
@@ -32,9 +32,40 @@ pub struct DinghyGeneralArgs {
     #[arg(long, short)]
     pub strip: bool,
 
-    /// Device hint
+    /// Device hint, can be passed multiple times to run on several devices
     #[arg(long, short)]
-    pub device: Option<String>,
+    pub device: Vec<String>,
+
+    /// Run on every device compatible with the selected platform
+    #[arg(long)]
+    pub all_devices: bool,
+
+    /// Kill the run after this many seconds, overriding the device and platform configuration
+    #[arg(long, value_name = "SECONDS")]
+    pub timeout: Option<u64>,
+
+    /// Start the executable under a debugger on the host instead of running it
+    #[arg(long)]
+    pub debugger: bool,
+
+    /// Command to run the executable through, with comma separated arguments (like
+    /// `taskset,-c,2`), overriding the device and platform configuration
+    #[arg(long, value_name = "COMMAND", value_delimiter = ',')]
+    pub launcher: Vec<String>,
+
+    /// Glob of files to copy back from the device after the run, relative to the bundle
+    /// directory, can be passed multiple times
+    #[arg(long, value_name = "GLOB")]
+    pub collect: Vec<String>,
+
+    /// Instrument the build for code coverage, and merge the profiles collected from the
+    /// devices into target/dinghy/coverage/<platform>.profdata
+    #[arg(long)]
+    pub coverage: bool,
+
+    /// Write a test report of each run in target/dinghy/reports, see cargo dinghy report
+    #[arg(long)]
+    pub report: bool,
 
     /// Either a dinghy subcommand (see cargo dinghy all-dinghy-subcommands) or a
     /// cargo one (see cargo --list)
@@ -51,13 +82,33 @@ pub struct SubCommandWrapper {
 #[derive(Subcommand, Debug)]
 pub enum DinghySubcommand {
     /// List devices that can be used with Dinghy for the selected platform
-    Devices {},
+    Devices {
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
     /// List all devices that can be used with Dinghy
-    AllDevices {},
+    AllDevices {
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
     /// List all platforms known to dinghy
-    AllPlatforms {},
+    AllPlatforms {
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
     /// List all available dinghy subcommands
     AllDinghySubcommands {},
+    /// Check the toolchains, platforms and devices setup, and suggest fixes
+    Doctor {},
+    /// Merge the test reports written with --report
+    Report {
+        /// Output JUnit XML instead of a summary
+        #[arg(long)]
+        junit: bool,
+    },
     /// Dinghy runner, used internally to run executables on targets
     Runner { args: Vec<String> },
     /// Build an artifact and run it on a target device using the provided wrapper
@@ -74,6 +125,15 @@ pub enum DinghySubcommand {
     },
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Human readable listing
+    #[default]
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
 #[derive(Debug)]
 pub enum DinghyMode {
     DinghySubcommand(DinghySubcommand),
@@ -102,7 +162,7 @@ impl DinghyCli {
         let args_taking_value = DinghyGeneralArgs::command()
             .get_arguments()
             .filter_map(|arg| {
-                if arg.get_value_names().is_some() {
+                if arg.get_action().takes_values() {
                     let mut values = vec![];
                     if let Some(shorts) = arg.get_short_and_visible_aliases() {
                         values
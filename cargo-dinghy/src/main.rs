@@ -1,28 +1,37 @@
 use std::convert::identity;
 use std::env;
 use std::env::current_dir;
-use std::io::BufReader;
+use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use cargo_metadata::camino::Utf8PathBuf;
 use cargo_metadata::Message;
 use log::{debug, error, info};
 
 use dinghy_lib::config::dinghy_config;
+use dinghy_lib::coverage;
+use dinghy_lib::doctor;
 use dinghy_lib::errors::*;
-use dinghy_lib::project::Project;
-use dinghy_lib::utils::{set_current_verbosity, user_facing_log, LogCommandExt};
-use dinghy_lib::Dinghy;
+use dinghy_lib::interrupt;
+use dinghy_lib::overlay::Overlayer;
+use dinghy_lib::project::{PackagesFilteredOut, Project};
+use dinghy_lib::report::{self, LibtestParser, Report, TestStatus};
+use dinghy_lib::utils::{interrupt_process, set_current_verbosity, user_facing_log, LogCommandExt};
 use dinghy_lib::Platform;
 use dinghy_lib::{Build, SetupArgs};
+use dinghy_lib::{Configuration, Dinghy};
 use dinghy_lib::{Device, Runnable};
 
-use crate::cli::{DinghyCli, DinghyMode, DinghySubcommand, SubCommandWrapper};
+use crate::cli::{DinghyCli, DinghyMode, DinghySubcommand, MessageFormat, SubCommandWrapper};
 
 mod cli;
 
+/// Exit code of the runner when a run is killed by its timeout, as with coreutils' `timeout`
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
 fn main() {
     env_logger::init_from_env(
         env_logger::Env::new()
@@ -36,8 +45,9 @@ fn main() {
 
     if let Err(e) = run_command(cli) {
         error!("{:?}", e);
-        // positively ugly.
-        if e.to_string().contains("are filtered out on platform") {
+        if interrupt::interrupted() {
+            std::process::exit(130)
+        } else if e.downcast_ref::<PackagesFilteredOut>().is_some() {
             std::process::exit(3)
         } else {
             std::process::exit(1)
@@ -48,12 +58,22 @@ fn main() {
 fn run_command(cli: DinghyCli) -> Result<()> {
     let conf = Arc::new(dinghy_config(current_dir()?)?);
 
+    if let DinghyMode::DinghySubcommand(DinghySubcommand::Doctor {}) = cli.mode {
+        // the doctor must work when probing fails, and outside of a cargo project
+        return run_doctor(&conf);
+    }
+
     let metadata = cargo_metadata::MetadataCommand::new().exec()?;
 
+    if let DinghyMode::DinghySubcommand(DinghySubcommand::Report { junit }) = cli.mode {
+        // the reports are read back from the target directory, the devices are not needed
+        return show_reports(metadata.target_directory.as_std_path(), junit);
+    }
+
     let project = Project::new(&conf, metadata);
     let dinghy = Dinghy::probe(&conf)?;
 
-    let (platform, device) = select_platform_and_device_from_cli(&cli, &dinghy)?;
+    let (platform, devices) = select_platform_and_devices_from_cli(&cli, &dinghy)?;
 
     let setup_args = SetupArgs {
         verbosity: cli.args.verbose as i8 - cli.args.quiet as i8,
@@ -61,17 +81,43 @@ fn run_command(cli: DinghyCli) -> Result<()> {
         envs: cli.args.env.clone(),
         cleanup: cli.args.cleanup,
         strip: cli.args.strip, // TODO this should probably be configurable in the config as well
-        device_id: device.as_ref().map(|d| d.id().to_string()),
+        device_ids: devices.iter().map(|d| d.id().to_string()).collect(),
+        timeout: cli.args.timeout,
+        debugger: cli.args.debugger,
+        launcher: cli.args.launcher.clone(),
+        collect: cli.args.collect.clone(),
+        coverage: cli.args.coverage,
+        report: cli.args.report,
     };
 
     match cli.mode {
         DinghyMode::CargoSubcommand { ref args } => {
-            let mut cmd = create_cargo_subcomand(&platform, &device, &project, &setup_args, args)?;
+            let args = project.filter_cargo_args(&**platform, args)?;
+            let mut cmd =
+                create_cargo_subcomand(&platform, &devices, &project, &setup_args, &args)?;
+            let target_path = PathBuf::from(project.metadata.target_directory.clone());
+            if cli.args.coverage {
+                let _ =
+                    std::fs::remove_dir_all(coverage::profiles_dir(&target_path, &platform.id()));
+                coverage::instrument(&mut cmd, platform.rustc_triple());
+            }
+            if cli.args.report {
+                report::clear_reports(&target_path)?;
+            }
 
             log::debug!("Launching {:?}", cmd);
             let status = cmd.log_invocation(2).status()?;
             log::debug!("done");
 
+            if cli.args.coverage {
+                // failed tests still tell what they covered
+                match coverage::merge(&target_path, &platform.id(), &conf.android) {
+                    Ok(profdata) => user_facing_log("Coverage", &profdata.display().to_string(), 0),
+                    Err(e) if status.success() => return Err(e),
+                    Err(e) => error!("{:?}", e),
+                }
+            }
+
             std::process::exit(status.code().unwrap_or_else(|| {
                 log::error!("Could not get cargo exit code");
                 -1
@@ -79,6 +125,7 @@ fn run_command(cli: DinghyCli) -> Result<()> {
         }
         DinghyMode::DinghySubcommand(DinghySubcommand::Runner { ref args }) => {
             debug!("starting dinghy runner, args {:?}", args);
+            interrupt::install_handler()?;
 
             let exe = args.first().cloned().unwrap();
             let exe_path = PathBuf::from(&exe);
@@ -102,9 +149,9 @@ fn run_command(cli: DinghyCli) -> Result<()> {
 
             debug!("inferred target {:?}", inferred_target);
 
-            let (mut final_platform, mut final_device) = (platform, device);
+            let (mut final_platform, mut final_devices) = (platform, devices);
             if let Some(inferred_target) = inferred_target {
-                if final_device.is_none()
+                if final_devices.is_empty()
                     && final_platform.rustc_triple() != inferred_target
                     && cli.args.platform.is_none()
                 {
@@ -116,14 +163,25 @@ fn run_command(cli: DinghyCli) -> Result<()> {
                         let device = find_first_device_for_platform(&cli, &dinghy, &platform);
                         if let Some(device) = device {
                             info!("Runner was called without explicit platform, we found {} and device {}", platform.id(), device.id());
-                            final_device = Some(device)
+                            final_devices = vec![device]
                         }
                         final_platform = platform;
                     }
                 }
             };
 
-            if let Some(device) = final_device {
+            if cli.args.debugger && final_devices.len() > 1 {
+                bail!("The debugger can only be started on one device")
+            }
+            if cli.args.debugger && cli.args.report {
+                bail!("The debugger can not be used with --report")
+            }
+            // the reports are parsed from the output of child runners
+            if final_devices.len() > 1 || (cli.args.report && !final_devices.is_empty()) {
+                return run_on_devices(&cli, &project, &final_platform, &final_devices, args);
+            }
+
+            if let Some(device) = final_devices.into_iter().next() {
                 user_facing_log(
                     "Targeting",
                     &format!(
@@ -133,14 +191,7 @@ fn run_command(cli: DinghyCli) -> Result<()> {
                     ),
                     0,
                 );
-                let exe_name = exe_path.file_name().unwrap().to_str().unwrap().to_string();
-                let exe_id = if exe_name == "rust_out" {
-                    // rustdoc may run concurent runners all with the same exe name, fortunately the parent dir is
-                    // different in that case so lets use that instead as an id
-                    exe_path.parent().unwrap().file_name().unwrap().to_str().unwrap().to_string()
-                } else {
-                    exe_name
-                };
+                let exe_id = runnable_id(&exe_path);
 
                 let (args, files_in_run_args): (Vec<String>, Vec<Option<PathBuf>>) = args
                     .into_iter()
@@ -169,14 +220,50 @@ fn run_command(cli: DinghyCli) -> Result<()> {
                 let files_in_run_args =
                     files_in_run_args.into_iter().filter_map(identity).collect();
 
+                // the stamp keeps the profiles of previous runs left on the device out
+                let coverage_stamp = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)?
+                    .as_millis();
+                let mut envs = cli.args.env.clone();
+                if cli.args.coverage {
+                    envs.push(format!(
+                        "LLVM_PROFILE_FILE={}",
+                        coverage::profile_file(coverage_stamp)
+                    ));
+                }
                 let args_ref = args.iter().map(|s| &s[..]).collect::<Vec<_>>();
-                let envs_ref = cli.args.env.iter().map(|s| &s[..]).collect::<Vec<_>>();
+                let envs_ref = envs.iter().map(|s| &s[..]).collect::<Vec<_>>();
                 final_platform.setup_env(&project, &setup_args)?;
 
+                let overlay_dirs = Overlayer::overlays(
+                    &conf
+                        .platform_configuration(&final_platform.id())
+                        .unwrap_or_default(),
+                    &final_platform.id(),
+                    &project,
+                )?
+                .into_iter()
+                .map(|it| it.path)
+                .collect::<Vec<_>>();
+                let sysroot = final_platform.sysroot()?;
+                let dynamic_libraries = dinghy_lib::elf::dynamic_libraries(
+                    &exe_path,
+                    &overlay_dirs,
+                    sysroot.as_deref(),
+                )?;
+                debug!("dynamic libraries {:?}", dynamic_libraries);
+
+                let launcher = Some(cli.args.launcher.clone())
+                    .filter(|it| !it.is_empty())
+                    .or_else(|| device.launcher())
+                    .or_else(|| {
+                        conf.platform_configuration(&final_platform.id())
+                            .and_then(|it| it.launcher.clone())
+                    })
+                    .unwrap_or_default();
                 let mut build = Build {
                     setup_args,
-                    // TODO these should be probably read from the executable file
-                    dynamic_libraries: vec![],
+                    dynamic_libraries,
                     runnable: Runnable {
                         id: exe_id,
                         package_name: std::env::var("CARGO_PKG_NAME")?,
@@ -187,27 +274,118 @@ fn run_command(cli: DinghyCli) -> Result<()> {
                     },
                     target_path: project.metadata.target_directory.clone().into(),
                     files_in_run_args,
+                    launcher,
+                    sysroot,
                 };
 
+                if cli.args.debugger {
+                    // the debugger on the host needs the symbols, so the executable is not stripped
+                    let bundle = device.debug_app(&project, &build, &args_ref, &envs_ref)?;
+                    if cli.args.cleanup {
+                        device.clean_app(&bundle)?;
+                    }
+                    return Ok(());
+                }
+
                 if cli.args.strip {
                     final_platform.strip(&mut build)?;
                 }
 
-                let bundle = device.run_app(
+                let timeout = cli
+                    .args
+                    .timeout
+                    .map(Duration::from_secs)
+                    .or_else(|| device.timeout())
+                    .or_else(|| {
+                        conf.platform_configuration(&final_platform.id())
+                            .and_then(|it| it.timeout)
+                            .map(Duration::from_secs)
+                    });
+                let watchdog = timeout.map(interrupt::watchdog);
+                let result = device.run_app(
                     &project, &build, &args_ref,
                     &envs_ref, // TODO these are also in the SetupArgs
-                )?;
+                );
+                drop(watchdog);
+
+                let collect = conf
+                    .collect
+                    .iter()
+                    .chain(&cli.args.collect)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let mut collect_error = None;
+                if let (Ok(outcome), true) = (&result, cli.args.coverage) {
+                    let profiles_dir =
+                        coverage::profiles_dir(&build.target_path, &final_platform.id())
+                            .join(device.id());
+                    // the coverage is incomplete without them, but the run itself went fine
+                    if let Err(e) = device.collect_files(
+                        &outcome.bundle,
+                        &[coverage::profile_glob(coverage_stamp)],
+                        &profiles_dir,
+                    ) {
+                        error!("No coverage from {}: {:#}", device.id(), e);
+                    }
+                }
+                if let (Ok(outcome), false) = (&result, collect.is_empty()) {
+                    // even after a failure, what the program left can help to understand it
+                    let collected_dir = dinghy_lib::device::collected_dir(&build, device.id());
+                    let _ = std::fs::remove_dir_all(&collected_dir);
+                    match device.collect_files(&outcome.bundle, &collect, &collected_dir) {
+                        Ok(files) => user_facing_log(
+                            "Collected",
+                            &format!(
+                                "{} file(s) from {} to {}",
+                                files.len(),
+                                device.id(),
+                                collected_dir.display()
+                            ),
+                            0,
+                        ),
+                        Err(e) if outcome.success() => collect_error = Some(e),
+                        Err(e) => error!("Collecting files after failed run failed: {:?}", e),
+                    }
+                }
 
-                // TODO this is not done if the run fails
                 if cli.args.cleanup {
-                    device.clean_app(&bundle)?;
+                    let bundle = match &result {
+                        Ok(outcome) => outcome.bundle.clone(),
+                        Err(_) => dinghy_lib::device::bundle_for(&build),
+                    };
+                    match (device.clean_app(&bundle), &result) {
+                        (Err(e), Ok(_)) => return Err(e),
+                        (Err(e), Err(_)) => error!("Cleanup after failed run failed: {:?}", e),
+                        (Ok(()), _) => {}
+                    }
+                }
+                if let Some(e) = collect_error {
+                    return Err(e);
+                }
+                if interrupt::timed_out() {
+                    error!(
+                        "{} on {} timed out after {}s",
+                        build.runnable.id,
+                        device.id(),
+                        timeout.unwrap_or_default().as_secs()
+                    );
+                    std::process::exit(TIMEOUT_EXIT_CODE);
+                }
+                let outcome = result?;
+                debug!("{} on {} {}", build.runnable.id, device.id(), outcome);
+                if interrupt::interrupted() {
+                    bail!("Interrupted {} on {}", build.runnable.id, device.id())
+                }
+                if !outcome.success() {
+                    error!("{} on {} {}", build.runnable.id, device.id(), outcome);
+                    std::process::exit(outcome.runner_exit_code());
                 }
             } else {
                 bail!("No device for platform {}", final_platform.id())
             }
             Ok(())
         }
-        DinghyMode::DinghySubcommand(DinghySubcommand::Devices {}) => {
+        DinghyMode::DinghySubcommand(DinghySubcommand::Devices { message_format }) => {
             match cli
                 .args
                 .platform
@@ -216,12 +394,20 @@ fn run_command(cli: DinghyCli) -> Result<()> {
             {
                 None => anyhow::bail!("No platform provided"),
                 Some(None) => anyhow::bail!("Unknown platform"),
-                Some(Some(platform)) => show_all_devices_for_platform(&dinghy, platform),
+                Some(Some(platform)) => {
+                    show_all_devices_for_platform(&dinghy, platform, message_format)
+                }
             }
         }
-        DinghyMode::DinghySubcommand(DinghySubcommand::AllDevices {}) => show_all_devices(&dinghy),
-        DinghyMode::DinghySubcommand(DinghySubcommand::AllPlatforms {}) => {
-            show_all_platforms(&dinghy)
+        DinghyMode::DinghySubcommand(DinghySubcommand::AllDevices { message_format }) => {
+            show_all_devices(&dinghy, message_format)
+        }
+        DinghyMode::DinghySubcommand(DinghySubcommand::AllPlatforms { message_format }) => {
+            show_all_platforms(&dinghy, message_format)
+        }
+        DinghyMode::DinghySubcommand(DinghySubcommand::Doctor {}) => run_doctor(&conf),
+        DinghyMode::DinghySubcommand(DinghySubcommand::Report { junit }) => {
+            show_reports(project.metadata.target_directory.as_std_path(), junit)
         }
         DinghyMode::DinghySubcommand(DinghySubcommand::AllDinghySubcommands {}) => {
             use clap::CommandFactory;
@@ -242,7 +428,7 @@ fn run_command(cli: DinghyCli) -> Result<()> {
             build_command.append(&mut lib_build_args);
 
             let mut build_cargo_cmd =
-                create_cargo_subcomand(&platform, &device, &project, &setup_args, &build_command)?;
+                create_cargo_subcomand(&platform, &devices, &project, &setup_args, &build_command)?;
 
             log::debug!("Launching {:?}", build_cargo_cmd);
             let mut child = build_cargo_cmd
@@ -379,6 +565,8 @@ fn run_command(cli: DinghyCli) -> Result<()> {
                     },
                     target_path: Default::default(),
                     files_in_run_args: vec![],
+                    launcher: vec![],
+                    sysroot: None,
                 };
                 platform.strip(&mut lib_build)?;
 
@@ -399,7 +587,7 @@ fn run_command(cli: DinghyCli) -> Result<()> {
                 args.push(extra_lib.to_string())
             }
             let mut run_cargo_cmd =
-                create_cargo_subcomand(&platform, &device, &project, &setup_args, &args)?;
+                create_cargo_subcomand(&platform, &devices, &project, &setup_args, &args)?;
 
             log::debug!("Launching {:?}", run_cargo_cmd);
             let status = run_cargo_cmd.log_invocation(2).status()?;
@@ -418,24 +606,29 @@ fn run_command(cli: DinghyCli) -> Result<()> {
 
 fn create_cargo_subcomand(
     platform: &Arc<Box<dyn Platform>>,
-    device: &Option<Arc<Box<dyn Device>>>,
+    devices: &[Arc<Box<dyn Device>>],
     project: &Project,
     setup_args: &SetupArgs,
     args: &Vec<String>,
 ) -> Result<Command> {
+    let device_ids = if devices.is_empty() {
+        "<none>".to_string()
+    } else {
+        devices
+            .iter()
+            .map(|it| it.id())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
     info!(
         "Targeting platform '{}' and device '{}'",
         platform.id(),
-        device.as_ref().map(|it| it.id()).unwrap_or("<none>")
+        device_ids
     );
 
     user_facing_log(
         "Targeting",
-        &format!(
-            "platform {} and device {}",
-            platform.id(),
-            device.as_ref().map(|it| it.id()).unwrap_or("<none>")
-        ),
+        &format!("platform {} and device {}", platform.id(), device_ids),
         0,
     );
 
@@ -453,29 +646,109 @@ fn create_cargo_subcomand(
     Ok(cmd)
 }
 
-fn show_all_platforms(dinghy: &Dinghy) -> Result<()> {
+fn run_doctor(conf: &Configuration) -> Result<()> {
+    let checks = doctor::diagnose(conf);
+    for check in &checks {
+        println!("{}", check);
+    }
+    let failures = checks
+        .iter()
+        .filter(|it| it.status == doctor::Status::Fail)
+        .count();
+    if failures > 0 {
+        bail!("{} check(s) failed", failures)
+    }
+    Ok(())
+}
+
+fn show_reports(target_path: &Path, junit: bool) -> Result<()> {
+    let reports = report::load_reports(target_path)?;
+    if junit {
+        print!("{}", report::junit(&reports));
+        return Ok(());
+    }
+    if reports.is_empty() {
+        println!(
+            "No test report in {}, run the tests with --report first",
+            report::reports_dir(target_path).display()
+        );
+    }
+    for report in &reports {
+        let mut summary = format!(
+            "{} on {}: {} passed, {} failed, {} ignored",
+            report.runnable,
+            report.device,
+            report.count(TestStatus::Passed),
+            report.count(TestStatus::Failed),
+            report.count(TestStatus::Ignored)
+        );
+        if report.failed() && report.count(TestStatus::Failed) == 0 {
+            match report.exit_code {
+                Some(code) => summary.push_str(&format!(", exited with code {}", code)),
+                None => summary.push_str(", killed"),
+            }
+        }
+        println!("{}", summary);
+        for test in &report.tests {
+            if test.status == TestStatus::Failed {
+                println!("    failed {}", test.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn platform_to_json(platform: &dyn Platform) -> json::JsonValue {
+    json::object! {
+        id: platform.id(),
+        rustc_triple: platform.rustc_triple(),
+        sysroot: platform
+            .sysroot()
+            .ok()
+            .flatten()
+            .map(|it| it.to_string_lossy().to_string()),
+        toolchain: platform.toolchain().map(|it| it.to_string_lossy().to_string()),
+    }
+}
+
+fn show_all_platforms(dinghy: &Dinghy, message_format: MessageFormat) -> Result<()> {
     let mut platforms = dinghy.platforms();
     platforms.sort_by(|str1, str2| str1.id().cmp(&str2.id()));
     for pf in platforms.iter() {
-        println!("* {} {}", pf.id(), pf.rustc_triple());
+        match message_format {
+            MessageFormat::Human => println!("* {} {}", pf.id(), pf.rustc_triple()),
+            MessageFormat::Json => println!("{}", platform_to_json(&***pf).dump()),
+        }
     }
     Ok(())
 }
 
-fn show_all_devices(dinghy: &Dinghy) -> Result<()> {
-    println!("List of available devices for all platforms:");
-    show_devices(&dinghy, None)
+fn show_all_devices(dinghy: &Dinghy, message_format: MessageFormat) -> Result<()> {
+    if message_format == MessageFormat::Human {
+        println!("List of available devices for all platforms:");
+    }
+    show_devices(&dinghy, None, message_format)
 }
 
-fn show_all_devices_for_platform(dinghy: &Dinghy, platform: Arc<Box<dyn Platform>>) -> Result<()> {
-    println!(
-        "List of available devices for platform '{}':",
-        platform.id()
-    );
-    show_devices(&dinghy, Some(platform))
+fn show_all_devices_for_platform(
+    dinghy: &Dinghy,
+    platform: Arc<Box<dyn Platform>>,
+    message_format: MessageFormat,
+) -> Result<()> {
+    if message_format == MessageFormat::Human {
+        println!(
+            "List of available devices for platform '{}':",
+            platform.id()
+        );
+    }
+    show_devices(&dinghy, Some(platform), message_format)
 }
 
-fn show_devices(dinghy: &Dinghy, platform: Option<Arc<Box<dyn Platform>>>) -> Result<()> {
+fn show_devices(
+    dinghy: &Dinghy,
+    platform: Option<Arc<Box<dyn Platform>>>,
+    message_format: MessageFormat,
+) -> Result<()> {
     let devices = dinghy
         .devices()
         .into_iter()
@@ -486,7 +759,7 @@ fn show_devices(dinghy: &Dinghy, platform: Option<Arc<Box<dyn Platform>>>) -> Re
         })
         .collect::<Vec<_>>();
 
-    if devices.is_empty() {
+    if devices.is_empty() && message_format == MessageFormat::Human {
         error!("No matching device found");
         println!("No matching device found");
     } else {
@@ -497,88 +770,368 @@ fn show_devices(dinghy: &Dinghy, platform: Option<Arc<Box<dyn Platform>>>) -> Re
                 .filter(|pf| pf.is_compatible_with(&**device))
                 .cloned()
                 .collect();
-            println!("{}: {:?}", device, pf);
+            let unusable = device.unusable_reason();
+            match message_format {
+                MessageFormat::Human => match &unusable {
+                    Some(reason) => println!("{}: unusable, {}", device, reason),
+                    None => println!("{}: {:?}", device, pf),
+                },
+                MessageFormat::Json => println!(
+                    "{}",
+                    json::object! {
+                        id: device.id(),
+                        name: device.name(),
+                        kind: device.kind().to_string(),
+                        platforms: pf.iter().map(|it| platform_to_json(&***it)).collect::<Vec<_>>(),
+                        unusable: unusable,
+                    }
+                    .dump()
+                ),
+            }
         }
     }
     Ok(())
 }
 
-fn select_platform_and_device_from_cli(
+fn select_platform_and_devices_from_cli(
     cli: &DinghyCli,
     dinghy: &Dinghy,
-) -> Result<(Arc<Box<dyn Platform>>, Option<Arc<Box<dyn Device>>>)> {
+) -> Result<(Arc<Box<dyn Platform>>, Vec<Arc<Box<dyn Device>>>)> {
     if let Some(platform_name) = cli.args.platform.as_ref() {
         let platform = dinghy
             .platform_by_name(platform_name)
             .ok_or_else(|| anyhow!("No '{}' platform found", platform_name))?;
 
-        let device = find_first_device_for_platform(cli, dinghy, &platform);
-
-        Ok((platform, device))
-    } else if let Some(device_filter) = cli.args.device.as_ref() {
-        let is_banned_auto_platform_id = |id: &str| -> bool {
-            id.contains("auto-android")
-                && (id.contains("min") || id.contains("latest") || id.contains("api"))
+        let devices = if cli.args.all_devices || cli.args.device.len() > 1 {
+            find_all_devices_for_platform(cli, dinghy, &platform)
+        } else {
+            find_first_device_for_platform(cli, dinghy, &platform)
+                .into_iter()
+                .collect()
         };
-        let devices = dinghy
-            .devices()
-            .into_iter()
-            .filter(move |it| {
-                format!("{:?}", it)
-                    .to_lowercase()
-                    .contains(&device_filter.to_lowercase())
-            })
-            .collect::<Vec<_>>();
+
+        Ok((platform, devices))
+    } else if let Some(device_filter) = cli.args.device.first() {
+        let devices = devices_matching(dinghy, device_filter);
         if devices.len() == 0 {
             bail!("No devices found for name hint `{}'", device_filter)
         }
-        devices
-            .into_iter()
+        let found = devices
+            .iter()
             .filter_map(|d| {
-                let pf = dinghy
+                let compatible = dinghy
                     .platforms()
-                    .iter()
-                    .filter(|pf| !is_banned_auto_platform_id(&pf.id()))
-                    .find(|pf| pf.is_compatible_with(&**d))
-                    .cloned();
+                    .into_iter()
+                    .filter(|pf| pf.is_compatible_with(&***d))
+                    .collect::<Vec<_>>();
+                let pf = d.preferred_platform(&compatible);
                 debug!(
                     "Looking for platform for {}: found {:?}",
                     d.id(),
                     pf.as_ref().map(|p| p.id())
                 );
-                pf.map(|it| (it, Some(d)))
-            })
-            .next()
-            .ok_or_else(|| {
-                anyhow!(
-                    "No device and platform combination found for device hint `{}'",
-                    device_filter
-                )
+                pf.map(|it| (it, d.clone()))
             })
+            .next();
+        let Some((platform, device)) = found else {
+            for device in &devices {
+                if let Some(reason) = device.unusable_reason() {
+                    bail!("Device {} can not be used: {}", device.id(), reason)
+                }
+                match device.detected_rustc_triples() {
+                    Ok(Some(triples)) => bail!(
+                        "Device {} runs {}, but no platform is configured for it",
+                        device.id(),
+                        triples.join(" or ")
+                    ),
+                    Ok(None) => (),
+                    Err(e) => {
+                        return Err(e.context(format!(
+                            "Could not detect the platform of device {}",
+                            device.id()
+                        )))
+                    }
+                }
+            }
+            bail!(
+                "No device and platform combination found for device hint `{}'",
+                device_filter
+            )
+        };
+        if cli.args.all_devices || cli.args.device.len() > 1 {
+            let devices = find_all_devices_for_platform(cli, dinghy, &platform);
+            Ok((platform, devices))
+        } else {
+            Ok((platform, vec![device]))
+        }
+    } else if cli.args.all_devices {
+        bail!("--all-devices needs a platform (-p) or a device hint (-d)")
     } else {
-        Ok((dinghy.host_platform(), None))
+        Ok((dinghy.host_platform(), vec![]))
     }
 }
 
+/// Devices matching a device hint. A hint that is exactly the id of a device only selects this
+/// device, otherwise it is matched against the device description.
+fn devices_matching(dinghy: &Dinghy, filter: &str) -> Vec<Arc<Box<dyn Device>>> {
+    let devices = dinghy.devices();
+    if devices.iter().any(|it| it.id() == filter) {
+        return devices.into_iter().filter(|it| it.id() == filter).collect();
+    }
+    devices
+        .into_iter()
+        .filter(|device| {
+            format!("{:?}", device)
+                .to_lowercase()
+                .contains(&filter.to_lowercase())
+        })
+        .collect()
+}
+
 fn find_first_device_for_platform(
     cli: &DinghyCli,
     dinghy: &Dinghy,
     platform: &Arc<Box<dyn Platform>>,
 ) -> Option<Arc<Box<dyn Device>>> {
-    dinghy
-        .devices()
-        .into_iter()
-        .filter(|device| {
-            cli.args
-                .device
-                .as_ref()
-                .map(|filter| {
-                    format!("{:?}", device)
-                        .to_lowercase()
-                        .contains(&filter.to_lowercase())
-                })
-                .unwrap_or(true)
-        })
-        .filter(|it| platform.is_compatible_with(&**it.as_ref()))
-        .next()
+    match cli.args.device.first() {
+        Some(filter) => devices_matching(dinghy, filter),
+        None => dinghy.devices(),
+    }
+    .into_iter()
+    .find(|it| platform.is_compatible_with(&**it.as_ref()))
+}
+
+/// All the devices compatible with the platform that match any of the device hints. When a hint
+/// is given without `--all-devices`, only the first device matching it is kept.
+fn find_all_devices_for_platform(
+    cli: &DinghyCli,
+    dinghy: &Dinghy,
+    platform: &Arc<Box<dyn Platform>>,
+) -> Vec<Arc<Box<dyn Device>>> {
+    let candidates_per_hint = if cli.args.device.is_empty() {
+        vec![dinghy.devices()]
+    } else {
+        cli.args
+            .device
+            .iter()
+            .map(|filter| devices_matching(dinghy, filter))
+            .collect()
+    };
+    let mut devices: Vec<Arc<Box<dyn Device>>> = vec![];
+    for candidates in candidates_per_hint {
+        let compatible = candidates
+            .into_iter()
+            .filter(|it| platform.is_compatible_with(&**it.as_ref()))
+            .filter(|it| !devices.iter().any(|known| known.id() == it.id()))
+            .take(if cli.args.all_devices { usize::MAX } else { 1 })
+            .collect::<Vec<_>>();
+        devices.extend(compatible);
+    }
+    devices
+}
+
+fn runnable_id(exe_path: &Path) -> String {
+    let exe_name = exe_path.file_name().unwrap().to_str().unwrap();
+    // the exe may have been stripped by a parent runner fanning out to several devices
+    let exe_name = exe_name
+        .strip_suffix("-stripped")
+        .unwrap_or(exe_name)
+        .to_string();
+    if exe_name == "rust_out" {
+        // rustdoc may run concurent runners all with the same exe name, fortunately the parent dir is
+        // different in that case so lets use that instead as an id
+        exe_path.parent().unwrap().file_name().unwrap().to_str().unwrap().to_string()
+    } else {
+        exe_name
+    }
+}
+
+/// Runs the executable on several devices concurrently, by spawning one runner per device, and
+/// prefixes their output with the device id. With `--report`, it also writes the report of each
+/// device from the output of its runner.
+fn run_on_devices(
+    cli: &DinghyCli,
+    project: &Project,
+    platform: &Arc<Box<dyn Platform>>,
+    devices: &[Arc<Box<dyn Device>>],
+    args: &[String],
+) -> Result<()> {
+    if devices.len() > 1 {
+        user_facing_log(
+            "Targeting",
+            &format!(
+                "platform {} and devices {}",
+                platform.id(),
+                devices
+                    .iter()
+                    .map(|it| it.id())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            0,
+        );
+    }
+
+    let mut args = args.to_vec();
+    if cli.args.strip {
+        // strip only once instead of letting every device runner do it on the same file
+        let setup_args = SetupArgs {
+            verbosity: cli.args.verbose as i8 - cli.args.quiet as i8,
+            forced_overlays: cli.args.overlay.clone(),
+            envs: cli.args.env.clone(),
+            cleanup: cli.args.cleanup,
+            strip: true,
+            device_ids: vec![],
+            timeout: None,
+            debugger: false,
+            launcher: vec![],
+            collect: vec![],
+            coverage: false,
+            report: false,
+        };
+        let mut build = Build {
+            setup_args,
+            dynamic_libraries: vec![],
+            runnable: Runnable {
+                id: "".to_string(),
+                package_name: "".to_string(),
+                exe: PathBuf::from(&args[0]).canonicalize()?,
+                source: Default::default(),
+                skip_source_copy: true,
+            },
+            target_path: project.metadata.target_directory.clone().into(),
+            files_in_run_args: vec![],
+            launcher: vec![],
+            sysroot: None,
+        };
+        platform.strip(&mut build)?;
+        args[0] = build.runnable.exe.to_string_lossy().to_string();
+    }
+
+    let spawn_runner = |device: &Arc<Box<dyn Device>>| -> Result<_> {
+        let mut command = Command::new(env::current_exe()?);
+        command.arg("-p").arg(platform.id());
+        command.arg("-d").arg(device.id());
+        for _ in 0..cli.args.verbose {
+            command.arg("-v");
+        }
+        for _ in 0..cli.args.quiet {
+            command.arg("-q");
+        }
+        for env in &cli.args.env {
+            command.arg("-e").arg(env);
+        }
+        if let Some(timeout) = cli.args.timeout {
+            command.arg("--timeout").arg(timeout.to_string());
+        }
+        if !cli.args.launcher.is_empty() {
+            command.arg("--launcher").arg(cli.args.launcher.join(","));
+        }
+        for glob in &cli.args.collect {
+            command.arg("--collect").arg(glob);
+        }
+        if cli.args.coverage {
+            command.arg("--coverage");
+        }
+        if cli.args.cleanup {
+            command.arg("--cleanup");
+        }
+        command.arg("runner").arg("--").args(&args);
+        let started = Instant::now();
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .log_invocation(2)
+            .spawn()?;
+        // a single device runner keeps its output as is
+        let prefix = if devices.len() > 1 {
+            format!("[{}] ", device.id())
+        } else {
+            String::new()
+        };
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let out_prefix = prefix.clone();
+        let out_forwarder = std::thread::spawn(move || {
+            let mut parser = LibtestParser::new();
+            for line in BufReader::new(stdout).lines().map_while(|it| it.ok()) {
+                println!("{}{}", out_prefix, line);
+                parser.line(&line);
+            }
+            parser
+        });
+        let err_forwarder = std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(|it| it.ok()) {
+                eprintln!("{}{}", prefix, line);
+            }
+        });
+        Ok((
+            device.id().to_string(),
+            started,
+            child,
+            out_forwarder,
+            err_forwarder,
+        ))
+    };
+    let mut children = vec![];
+    for device in devices {
+        match spawn_runner(device) {
+            Ok(child) => children.push(child),
+            Err(e) => {
+                // the runners already started kill what they run on their device on SIGINT
+                for (_, _, mut child, out_forwarder, err_forwarder) in children {
+                    interrupt_process(child.id());
+                    let _ = child.wait();
+                    let _ = out_forwarder.join();
+                    let _ = err_forwarder.join();
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    let mut failed = vec![];
+    let mut reports = vec![];
+    for (device_id, started, mut child, out_forwarder, err_forwarder) in children {
+        let status = child.wait()?;
+        let duration = started.elapsed();
+        let parser = out_forwarder.join();
+        let _ = err_forwarder.join();
+        if let (true, Ok(parser)) = (cli.args.report, parser) {
+            reports.push(Report {
+                runnable: runnable_id(Path::new(&args[0])),
+                package_name: env::var("CARGO_PKG_NAME").unwrap_or_default(),
+                platform: platform.id(),
+                device: device_id.clone(),
+                duration: duration.as_secs_f64(),
+                exit_code: status.code(),
+                tests: parser.finish(),
+            });
+        }
+        if !status.success() {
+            failed.push((device_id, status.code().unwrap_or(1)));
+        }
+    }
+    let target_path = PathBuf::from(project.metadata.target_directory.clone());
+    for report in reports {
+        let path = report.write(&target_path)?;
+        debug!("Wrote test report {}", path.display());
+    }
+
+    match failed.first() {
+        None => Ok(()),
+        Some((_, code)) => {
+            if devices.len() > 1 {
+                error!(
+                    "Run failed on device(s) {}",
+                    failed
+                        .iter()
+                        .map(|(id, _)| id.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            std::process::exit(*code)
+        }
+    }
 }
@@ -1,5 +1,10 @@
-use crate::device::make_remote_app;
+use crate::android::{AdbDeviceEntry, ABIS};
+use crate::device::{
+    files_to_collect, make_remote_app, remote_find_command, remote_kill_command, shell_launcher,
+    REMOTE_PID_RECORDER,
+};
 use crate::errors::*;
+use crate::interrupt;
 use crate::platform::regular_platform::RegularPlatform;
 use crate::project::Project;
 use crate::utils::{get_current_verbosity, path_to_str, user_facing_log, LogCommandExt};
@@ -7,57 +12,103 @@ use crate::Build;
 use crate::BuildBundle;
 use crate::Device;
 use crate::DeviceCompatibility;
+use crate::DeviceKind;
+use crate::Platform;
+use crate::RunOutcome;
+use fs_err as fs;
 use log::{debug, info, log_enabled};
-use std::io::Write;
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use std::time::Instant;
 use std::{fmt, io, path, process};
 
 static ANDROID_WORK_DIR: &str = "/data/local/tmp/dinghy";
 
+/// Echoed after the program by devices without the shell protocol, as their `adb shell` always
+/// exits with 0.
+static EXIT_CODE_MARKER: &str = "FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=";
+
 #[derive(Clone)]
 pub struct AndroidDevice {
     pub adb: path::PathBuf,
     pub id: String,
     pub supported_targets: Vec<&'static str>,
+    /// The SDK version of the system, from `ro.build.version.sdk`
+    pub api_level: Option<u32>,
+    pub model: Option<String>,
+    pub product: Option<String>,
+    pub transport_id: Option<String>,
+    pub emulator: bool,
+    /// Why the device can not be used, when it is listed anyway
+    pub unusable: Option<String>,
 }
 
 impl AndroidDevice {
+    /// The device of an `adb devices -l` entry. A device which can not be used, because it is
+    /// unauthorized, offline or has no ABI rust targets, is kept along with the reason.
+    pub(crate) fn from_entry(adb: path::PathBuf, entry: &AdbDeviceEntry) -> AndroidDevice {
+        let device = match entry.state.as_str() {
+            "device" => AndroidDevice::from_id(adb.clone(), &entry.serial),
+            "unauthorized" => Err(anyhow!(
+                "unauthorized, accept the USB debugging prompt on the device"
+            )),
+            state => Err(anyhow!("{}", state)),
+        };
+        let mut device = device
+            .and_then(|device| {
+                if device.supported_targets.is_empty() {
+                    bail!("none of its ABIs is supported")
+                }
+                Ok(device)
+            })
+            .unwrap_or_else(|e| AndroidDevice {
+                adb,
+                id: entry.serial.clone(),
+                supported_targets: vec![],
+                api_level: None,
+                model: None,
+                product: None,
+                transport_id: None,
+                emulator: false,
+                unusable: Some(format!("{:#}", e)),
+            });
+        device.model = entry.model.clone();
+        device.product = entry.product.clone();
+        device.transport_id = entry.transport_id.clone();
+        device.emulator = entry.emulator;
+        device
+    }
+
     pub fn from_id(adb: path::PathBuf, id: &str) -> Result<AndroidDevice> {
         for prop in &[
             "ro.product.cpu.abilist",
             "ro.product.cpu.abi",
             "ro.product.cpu.abi2",
         ] {
-            let getprop_output = process::Command::new(&adb)
-                .args(&["-s", id, "shell", "getprop", prop])
-                .log_invocation(3)
-                .output()?;
-            let abilist = String::from_utf8(getprop_output.stdout)?;
-            debug!(
-                "Android device {}, getprop {} returned {}",
-                id,
-                prop,
-                abilist.trim()
-            );
+            let abilist = getprop(&adb, id, prop)?;
             if abilist.trim().len() > 0 {
                 let supported_targets = abilist
                     .trim()
                     .split(",")
                     .filter_map(|abi| {
-                        Some(match abi {
-                            "arm64-v8a" => "aarch64-linux-android",
-                            "armeabi-v7a" => "armv7-linux-androideabi",
-                            "armeabi" => "arm-linux-androideabi",
-                            "x86" => "i686-linux-android",
-                            "x86_64" => "x86_64-linux-android",
-                            _ => return None,
-                        })
+                        ABIS.iter()
+                            .find(|it| it.name == abi)
+                            .map(|it| it.rustc_triple)
                     })
                     .collect::<Vec<_>>();
 
+                let api_level = getprop(&adb, id, "ro.build.version.sdk")?.parse().ok();
+
                 return Ok(AndroidDevice {
                     adb,
                     id: id.into(),
                     supported_targets: supported_targets,
+                    api_level,
+                    model: None,
+                    product: None,
+                    transport_id: None,
+                    emulator: false,
+                    unusable: None,
                 });
             }
         }
@@ -157,10 +208,120 @@ impl AndroidDevice {
     fn to_remote_bundle(build_bundle: &BuildBundle) -> Result<BuildBundle> {
         build_bundle.replace_prefix_with(ANDROID_WORK_DIR)
     }
+
+    /// Whether `adb shell` forwards the exit code of the command, and keeps stderr apart.
+    fn supports_shell_v2(&self) -> bool {
+        let features = self
+            .adb()
+            .and_then(|mut adb| Ok(adb.arg("features").log_invocation(3).output()?));
+        match features {
+            Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .any(|it| it.trim() == "shell_v2"),
+            _ => false,
+        }
+    }
+
+    /// Runs a shell command on the device, streaming its output, and returns its exit code.
+    fn run_shell(&self, command: &str) -> Result<i32> {
+        if self.supports_shell_v2() {
+            let status = self
+                .adb()?
+                .arg("shell")
+                .arg(command)
+                .log_invocation(1)
+                .status()?;
+            return status
+                .code()
+                .ok_or_else(|| anyhow!("adb was killed by a signal"));
+        }
+
+        let mut child = self
+            .adb()?
+            .arg("shell")
+            .arg(format!("{} ; echo {}$?", command, EXIT_CODE_MARKER))
+            .stdout(process::Stdio::piped())
+            .log_invocation(1)
+            .spawn()?;
+        let mut stdout = io::BufReader::new(child.stdout.take().unwrap());
+        let mut exit_code = None;
+        let mut line = vec![];
+        loop {
+            line.clear();
+            if stdout.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let (output, code) = split_exit_code(&line);
+            exit_code = code.or(exit_code);
+            // the output is written as it comes, a program killed by a timeout still shows it
+            let mut out = io::stdout().lock();
+            let _ = out.write_all(output);
+            let _ = out.flush();
+        }
+        if !child.wait()?.success() {
+            bail!("adb failed")
+        }
+        exit_code.ok_or_else(|| anyhow!("no exit code in the output of adb"))
+    }
+}
+
+fn getprop(adb: &path::Path, id: &str, prop: &str) -> Result<String> {
+    let output = process::Command::new(adb)
+        .args(&["-s", id, "shell", "getprop", prop])
+        .log_invocation(3)
+        .output()?;
+    let value = String::from_utf8(output.stdout)?.trim().to_string();
+    debug!("Android device {}, getprop {} returned {}", id, prop, value);
+    Ok(value)
+}
+
+/// The API level a platform builds for, from the suffix of its compiler, like
+/// `aarch64-linux-android30`.
+fn platform_api_level(platform: &RegularPlatform) -> Option<u32> {
+    let cc_prefix = &platform.toolchain.cc_prefix;
+    let api = &cc_prefix[cc_prefix
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len()..];
+    api.parse().ok()
+}
+
+/// The API level of the `auto-android-<cpu>-api<level>` platforms.
+fn auto_platform_api_level(platform_id: &str) -> Option<u32> {
+    platform_id
+        .strip_prefix("auto-android-")?
+        .rsplit_once("-api")?
+        .1
+        .parse()
+        .ok()
+}
+
+/// Cuts the exit code echoed after the program out of an output line, whatever the program
+/// printed before it without a new line.
+fn split_exit_code(line: &[u8]) -> (&[u8], Option<i32>) {
+    let marker = EXIT_CODE_MARKER.as_bytes();
+    let Some(start) = line.windows(marker.len()).position(|it| it == marker) else {
+        return (line, None);
+    };
+    let code = String::from_utf8_lossy(&line[start + marker.len()..])
+        .trim()
+        .parse()
+        .ok();
+    (&line[..start], code)
 }
 
 impl DeviceCompatibility for AndroidDevice {
     fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        if self.unusable.is_some() {
+            return false;
+        }
+        // executables built for a newer system may need symbols missing from the device
+        if let (Some(device_api), Some(platform_api)) =
+            (self.api_level, platform_api_level(platform))
+        {
+            if platform_api > device_api {
+                return false;
+            }
+        }
         if platform.id.starts_with("auto-android") {
             let cpu = platform.id.split("-").nth(2).unwrap();
             self.supported_targets
@@ -204,6 +365,48 @@ impl Device for AndroidDevice {
         Ok(())
     }
 
+    fn collect_files(
+        &self,
+        build_bundle: &BuildBundle,
+        patterns: &[String],
+        destination: &path::Path,
+    ) -> Result<Vec<path::PathBuf>> {
+        let remote_bundle = AndroidDevice::to_remote_bundle(build_bundle)?;
+        let bundle_dir = path_to_str(&remote_bundle.bundle_dir)?;
+        let listing = self
+            .adb()?
+            .arg("shell")
+            .arg(remote_find_command(bundle_dir))
+            .log_invocation(2)
+            .output()?;
+        if !listing.status.success() {
+            bail!("Could not list the files of {} on {}", bundle_dir, self.id)
+        }
+        let files = files_to_collect(&String::from_utf8_lossy(&listing.stdout), patterns)?;
+        let mut dirs = files
+            .iter()
+            .filter_map(|it| it.parent())
+            .collect::<Vec<_>>();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            fs::create_dir_all(destination.join(dir))?;
+            let mut command = self.adb()?;
+            command.arg("pull");
+            for file in files.iter().filter(|it| it.parent() == Some(dir)) {
+                command.arg(remote_bundle.bundle_dir.join(file));
+            }
+            command.arg(destination.join(dir));
+            if !log_enabled!(::log::Level::Debug) {
+                command.stdout(::std::process::Stdio::null());
+            }
+            if !command.log_invocation(1).status()?.success() {
+                bail!("Error collecting files from {} on {}", bundle_dir, self.id)
+            }
+        }
+        Ok(files)
+    }
+
     fn debug_app(
         &self,
         _project: &Project,
@@ -211,34 +414,78 @@ impl Device for AndroidDevice {
         _args: &[&str],
         _envs: &[&str],
     ) -> Result<BuildBundle> {
-        unimplemented!()
+        bail!("Debugging is not supported on android devices")
     }
 
     fn id(&self) -> &str {
         &self.id
     }
 
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Android
+    }
+
     fn name(&self) -> &str {
         "android device"
     }
 
+    fn unusable_reason(&self) -> Option<String> {
+        self.unusable.clone()
+    }
+
+    /// The auto platform with the highest API level the device supports, for its preferred ABI,
+    /// or else the first platform which is not one of the `-api<level>`, `-min` and `-latest`
+    /// variants.
+    fn preferred_platform(
+        &self,
+        platforms: &[Arc<Box<dyn Platform>>],
+    ) -> Option<Arc<Box<dyn Platform>>> {
+        let abi_rank = |platform_id: &str| {
+            let cpu = platform_id.split('-').nth(2).unwrap_or_default();
+            self.supported_targets
+                .iter()
+                .position(|target| target.starts_with(cpu))
+                .unwrap_or(usize::MAX)
+        };
+        let best_api = platforms
+            .iter()
+            .filter_map(|pf| Some((auto_platform_api_level(&pf.id())?, pf)))
+            .filter(|(api, _)| self.api_level.is_some_and(|device_api| *api <= device_api))
+            .max_by_key(|(api, pf)| (*api, std::cmp::Reverse(abi_rank(&pf.id()))));
+        if let Some((_, platform)) = best_api {
+            return Some(platform.clone());
+        }
+        platforms
+            .iter()
+            .find(|pf| {
+                let id = pf.id();
+                !(id.starts_with("auto-android")
+                    && (id.ends_with("-min")
+                        || id.ends_with("-latest")
+                        || auto_platform_api_level(&id).is_some()))
+            })
+            .cloned()
+    }
+
     fn run_app(
         &self,
         project: &Project,
         build: &Build,
         args: &[&str],
         envs: &[&str],
-    ) -> Result<BuildBundle> {
+    ) -> Result<RunOutcome> {
         let args: Vec<String> = args
             .iter()
             .map(|&a| ::shell_escape::escape(a.into()).to_string())
             .collect();
         let (build_bundle, remote_bundle) = self.install_app(&project, &build)?;
         let command = format!(
-                "cd '{}'; RUST_BACKTRACE=1 {} DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} ; echo FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=$?",
+                "cd '{}'; RUST_BACKTRACE=1 {} DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {} {}",
                 path_to_str(&remote_bundle.bundle_dir)?,
                 envs.join(" "),
                 path_to_str(&remote_bundle.lib_dir)?,
+                REMOTE_PID_RECORDER,
+                shell_launcher(build),
                 path_to_str(&remote_bundle.bundle_exe)?,
                 args.join(" "));
         info!("Run {} on {}", build.runnable.id, self.id);
@@ -253,39 +500,46 @@ impl Device for AndroidDevice {
             );
         }
 
-        if !self
-            .adb()?
-            .arg("shell")
-            .arg(&command)
-            .log_invocation(1)
-            .output()
-            .with_context(|| format!("Couldn't run {} using adb.", build.runnable.exe.display()))
-            .and_then(|output| {
-                if output.status.success() {
-                    let _ = io::stdout().write(output.stdout.as_slice());
-                    let _ = io::stderr().write(output.stderr.as_slice());
-                    String::from_utf8(output.stdout).with_context(|| {
-                        format!("Couldn't run {} using adb.", build.runnable.exe.display())
-                    })
-                } else {
-                    bail!("Couldn't run {} using adb.", build.runnable.exe.display())
-                }
-            })
-            .map(|output| output.lines().last().unwrap_or("").to_string())
-            .map(|last_line| {
-                last_line.contains("FORWARD_RESULT_TO_DINGHY_BECAUSE_ADB_DOES_NOT=0")
-            })?
-        {
-            bail!("Failed")
-        }
+        let kill_command = remote_kill_command(path_to_str(&remote_bundle.bundle_dir)?);
+        let device = self.clone();
+        let _kill_on_interrupt = interrupt::on_interrupt(move || {
+            debug!("Killing the remote program on {}", device.id);
+            let _ = device.adb().and_then(|mut adb| {
+                Ok(adb
+                    .arg("shell")
+                    .arg(&kill_command)
+                    .log_invocation(1)
+                    .status()?)
+            });
+        });
+        let started = Instant::now();
+        let exit_code = self
+            .run_shell(&command)
+            .with_context(|| format!("Couldn't run {} using adb.", build.runnable.exe.display()))?;
 
-        Ok(build_bundle)
+        Ok(RunOutcome::from_shell_exit_code(
+            build_bundle,
+            exit_code,
+            started,
+        ))
     }
 }
 
 impl fmt::Display for AndroidDevice {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "Android/{}", self.id)
+        write!(fmt, "Android/{}", self.id)?;
+        let details = vec![
+            self.model.clone(),
+            self.emulator.then(|| "emulator".to_string()),
+            self.api_level.map(|it| format!("API {}", it)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if !details.is_empty() {
+            write!(fmt, " ({})", details.join(", "))?;
+        }
+        Ok(())
     }
 }
 
@@ -293,10 +547,34 @@ impl fmt::Debug for AndroidDevice {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         Ok(fmt.write_str(
             format!(
-                "Android {{ \"id\": \"{}\", \"supported_targets\": {:?} }}",
-                self.id, self.supported_targets
+                "Android {{ \"id\": \"{}\", \"supported_targets\": {:?}, \"api_level\": {:?}, \"model\": {:?}, \"product\": {:?}, \"transport_id\": {:?}, \"emulator\": {} }}",
+                self.id,
+                self.supported_targets,
+                self.api_level,
+                self.model,
+                self.product,
+                self.transport_id,
+                self.emulator
             )
             .as_str(),
         )?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_exit_code() {
+        assert_eq!(
+            split_exit_code(b"test result: ok\r\n"),
+            (&b"test result: ok\r\n"[..], None)
+        );
+        let line = format!("no new line{}101\r\n", EXIT_CODE_MARKER);
+        assert_eq!(
+            split_exit_code(line.as_bytes()),
+            (&b"no new line"[..], Some(101))
+        );
+    }
+}
@@ -1,13 +1,14 @@
-use crate::config::PlatformConfiguration;
+use crate::config::AndroidConfiguration;
 use crate::toolchain::ToolchainConfig;
-use crate::{Device, Platform, PlatformManager, Result};
+use crate::{Configuration, Device, Platform, PlatformManager, Result};
 use fs_err as fs;
 use std::fs::FileType;
-use std::{env, path, process};
+use std::{env, path, process, sync};
 
 pub use self::device::AndroidDevice;
 
 use crate::android::platform::AndroidPlatform;
+use crate::doctor::Check;
 use crate::utils::LogCommandExt;
 use anyhow::{anyhow, bail, Context};
 use log::debug;
@@ -15,33 +16,82 @@ use log::debug;
 mod device;
 mod platform;
 
+/// An ABI of android devices, and how rust and the NDK call it.
+pub(crate) struct AndroidAbi {
+    /// As in `ro.product.cpu.abilist`
+    pub name: &'static str,
+    pub rustc_triple: &'static str,
+    /// Prefix of the NDK compilers, before the API level, or `None` when the NDK no longer
+    /// supports the ABI
+    pub cc_triple: Option<&'static str>,
+    /// Prefix of the NDK binutils, and directory of the ABI in the NDK sysroot
+    pub ndk_triple: &'static str,
+}
+
+/// The ABIs rust supports. Their `auto-android-<cpu>` platforms exist when the NDK has a
+/// sysroot directory for them.
+pub(crate) const ABIS: &[AndroidAbi] = &[
+    AndroidAbi {
+        name: "arm64-v8a",
+        rustc_triple: "aarch64-linux-android",
+        cc_triple: Some("aarch64-linux-android"),
+        ndk_triple: "aarch64-linux-android",
+    },
+    AndroidAbi {
+        name: "armeabi-v7a",
+        rustc_triple: "armv7-linux-androideabi",
+        cc_triple: Some("armv7a-linux-androideabi"),
+        ndk_triple: "arm-linux-androideabi",
+    },
+    AndroidAbi {
+        name: "armeabi",
+        rustc_triple: "arm-linux-androideabi",
+        cc_triple: None,
+        ndk_triple: "arm-linux-androideabi",
+    },
+    AndroidAbi {
+        name: "x86",
+        rustc_triple: "i686-linux-android",
+        cc_triple: Some("i686-linux-android"),
+        ndk_triple: "i686-linux-android",
+    },
+    AndroidAbi {
+        name: "x86_64",
+        rustc_triple: "x86_64-linux-android",
+        cc_triple: Some("x86_64-linux-android"),
+        ndk_triple: "x86_64-linux-android",
+    },
+    AndroidAbi {
+        name: "riscv64",
+        rustc_triple: "riscv64-linux-android",
+        cc_triple: Some("riscv64-linux-android"),
+        ndk_triple: "riscv64-linux-android",
+    },
+];
+
 pub struct AndroidManager {
     adb: path::PathBuf,
+    conf: sync::Arc<Configuration>,
 }
 
 impl PlatformManager for AndroidManager {
     fn devices(&self) -> Result<Vec<Box<dyn Device>>> {
         let result = process::Command::new(&self.adb)
             .arg("devices")
+            .arg("-l")
             .log_invocation(3)
             .output()?;
         let mut devices = vec![];
-        let device_regex = ::regex::Regex::new(r#"^(\S+)\tdevice\r?$"#)?;
-        for line in String::from_utf8(result.stdout)?.split("\n").skip(1) {
-            if let Some(caps) = device_regex.captures(line) {
-                let d = AndroidDevice::from_id(self.adb.clone(), &caps[1])?;
-                debug!(
-                    "Discovered Android device {} ({:?})",
-                    d, d.supported_targets
-                );
-                devices.push(Box::new(d) as Box<dyn Device>);
-            }
+        for entry in parse_adb_devices(&String::from_utf8(result.stdout)?) {
+            let d = AndroidDevice::from_entry(self.adb.clone(), &entry);
+            debug!("Discovered Android device {:?}", d);
+            devices.push(Box::new(d) as Box<dyn Device>);
         }
         Ok(devices)
     }
     fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
-        if let Some(ndk) = ndk()? {
-            let default_api_level = "21";
+        if let Some(ndk) = ndk(&self.conf.android)? {
+            let default_api_level = self.conf.android.api_level.unwrap_or(21);
             debug!("Android NDK: {:?}", ndk);
             let version = ndk_version(&ndk)?;
             let major = version
@@ -72,17 +122,23 @@ impl PlatformManager for AndroidManager {
                 let bin = tools.path().join("bin");
                 debug!("Android tools bin: {:?}", bin);
                 let libclang_path = tools.path().join(if major >= 26 { "lib" } else { "lib64" });
-                for (rustc_cpu, cc_cpu, binutils_cpu, abi_kind) in &[
-                    ("aarch64", "aarch64", "aarch64", "android"),
-                    ("armv7", "armv7a", "arm", "androideabi"),
-                    ("i686", "i686", "i686", "android"),
-                    ("x86_64", "x86_64", "x86_64", "android"),
-                ] {
+                for abi in ABIS {
+                    let Some(cc_triple) = abi.cc_triple else {
+                        continue;
+                    };
+                    if let Some(abis) = &self.conf.android.abis {
+                        if !abis.iter().any(|it| it == abi.name) {
+                            continue;
+                        }
+                    }
+                    let lib_dir = tools.path().join("sysroot/usr/lib").join(abi.ndk_triple);
+                    if !lib_dir.is_dir() {
+                        debug!("No {} ABI in the NDK, no {:?}", abi.name, lib_dir);
+                        continue;
+                    }
+                    let rustc_cpu = abi.rustc_triple.split('-').next().unwrap();
                     let mut api_levels: Vec<String> = Vec::new();
-                    for entry in fs::read_dir(tools.path().join(format!(
-                        "sysroot/usr/lib/{}-linux-{}",
-                        binutils_cpu, abi_kind
-                    )))? {
+                    for entry in fs::read_dir(lib_dir)? {
                         let entry = entry?;
                         if entry.file_type()?.is_dir() {
                             let folder_name = entry.file_name().into_string().unwrap();
@@ -92,21 +148,23 @@ impl PlatformManager for AndroidManager {
                             }
                         }
                     }
-                    api_levels.sort();
+                    api_levels.sort_by_key(|it| it.parse::<u32>().unwrap());
                     let create_platform = |api: &str, suffix: &str| {
                         let id = format!("auto-android-{}{}", rustc_cpu, suffix);
                         let tc = ToolchainConfig {
                             bin_dir: bin.clone(),
-                            rustc_triple: format!("{}-linux-{}", rustc_cpu, abi_kind),
+                            rustc_triple: abi.rustc_triple.to_string(),
                             root: prebuilt.clone(),
                             sysroot: Some(tools.path().join("sysroot")),
                             cc: "clang".to_string(),
                             cxx: "clang++".to_string(),
-                            binutils_prefix: format!("{}-linux-{}", binutils_cpu, abi_kind),
-                            cc_prefix: format!("{}-linux-{}{}", cc_cpu, abi_kind, api),
+                            binutils_prefix: abi.ndk_triple.to_string(),
+                            cc_prefix: format!("{}{}", cc_triple, api),
                         };
+                        let configuration =
+                            self.conf.platform_configuration(&id).unwrap_or_default();
                         AndroidPlatform::new(
-                            PlatformConfiguration::default(),
+                            configuration,
                             id,
                             tc,
                             major,
@@ -131,7 +189,13 @@ impl PlatformManager for AndroidManager {
                             "-latest",
                         )?);
                     }
-                    platforms.push(create_platform(default_api_level, "")?);
+                    // ABIs newer than the default API level start at a higher one
+                    let plain_api_level = api_levels
+                        .first()
+                        .filter(|min| min.parse::<u32>().unwrap() > default_api_level)
+                        .cloned()
+                        .unwrap_or(default_api_level.to_string());
+                    platforms.push(create_platform(&plain_api_level, "")?);
                 }
                 return Ok(platforms);
             }
@@ -140,12 +204,65 @@ impl PlatformManager for AndroidManager {
     }
 }
 
+/// A device listed by `adb devices -l`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct AdbDeviceEntry {
+    pub serial: String,
+    /// `device` when it can be used, or `unauthorized`, `offline`, `no permissions (...)`...
+    pub state: String,
+    pub model: Option<String>,
+    pub product: Option<String>,
+    pub transport_id: Option<String>,
+    pub emulator: bool,
+}
+
+/// Parses the output of `adb devices -l`, like
+/// `emulator-5554 device product:sdk_gphone64_x86_64 model:sdk_gphone64_x86_64 transport_id:1`.
+pub(crate) fn parse_adb_devices(output: &str) -> Vec<AdbDeviceEntry> {
+    let is_property = |token: &str| {
+        token.split_once(':').is_some_and(|(key, _)| {
+            ["usb", "product", "model", "device", "transport_id"].contains(&key)
+        })
+    };
+    let mut entries = vec![];
+    for line in output.lines() {
+        let mut tokens = line.split_whitespace();
+        let Some(serial) = tokens.next() else {
+            continue;
+        };
+        if line.starts_with("List of devices") || line.starts_with('*') {
+            continue;
+        }
+        let tokens = tokens.collect::<Vec<_>>();
+        // the state may have spaces, like `no permissions (...)`
+        let state_len = tokens
+            .iter()
+            .position(|it| is_property(it))
+            .unwrap_or(tokens.len());
+        let property = |key: &str| {
+            tokens[state_len..]
+                .iter()
+                .find_map(|it| it.strip_prefix(key)?.strip_prefix(':'))
+                .map(|it| it.to_string())
+        };
+        entries.push(AdbDeviceEntry {
+            serial: serial.to_string(),
+            state: tokens[..state_len].join(" "),
+            model: property("model"),
+            product: property("product"),
+            transport_id: property("transport_id"),
+            emulator: serial.starts_with("emulator-"),
+        });
+    }
+    entries
+}
+
 impl AndroidManager {
-    pub fn probe() -> Option<AndroidManager> {
+    pub fn probe(conf: sync::Arc<Configuration>) -> Option<AndroidManager> {
         match adb() {
             Ok(adb) => {
                 debug!("ADB found: {:?}", adb);
-                Some(AndroidManager { adb })
+                Some(AndroidManager { adb, conf })
             }
             Err(_) => {
                 debug!("adb not found in path, android disabled");
@@ -155,6 +272,110 @@ impl AndroidManager {
     }
 }
 
+/// Checks adb, the NDK and the state of the connected devices.
+pub(crate) fn diagnose(conf: &Configuration, checks: &mut Vec<Check>) {
+    match adb() {
+        Ok(adb) => {
+            checks.push(Check::pass("android", format!("adb {}", adb.display())));
+            diagnose_adb_devices(&adb, checks);
+        }
+        Err(_) => checks.push(
+            Check::warn("android", "adb not found, android devices are disabled").with_hint(
+                "install the android platform-tools, or set DINGHY_ANDROID_ADB to the adb path",
+            ),
+        ),
+    }
+    let ndk = match ndk(&conf.android) {
+        Ok(Some(ndk)) => ndk,
+        Ok(None) => {
+            checks.push(
+                Check::warn("android", "NDK not found, android platforms are disabled")
+                    .with_hint("install the NDK with the SDK manager, or set ANDROID_NDK_HOME"),
+            );
+            return;
+        }
+        Err(e) => {
+            checks.push(Check::fail(
+                "android",
+                format!("looking for the NDK: {}", e),
+            ));
+            return;
+        }
+    };
+    let version = match ndk_version(&ndk) {
+        Ok(version) => version,
+        Err(e) => {
+            checks.push(
+                Check::fail("android", format!("{:#}", e))
+                    .with_hint("check that ANDROID_NDK_HOME points to an NDK"),
+            );
+            return;
+        }
+    };
+    let major = version
+        .split('.')
+        .next()
+        .and_then(|it| it.parse::<usize>().ok());
+    if major.is_none_or(|major| major < 19) {
+        checks.push(
+            Check::fail(
+                "android",
+                format!("NDK {} at {:?} is not supported", version, ndk),
+            )
+            .with_hint("install NDK 19 or later"),
+        );
+        return;
+    }
+    let prebuilt = ndk.join("toolchains/llvm/prebuilt");
+    let has_tools = prebuilt
+        .read_dir()
+        .map(|mut entries| entries.any(|it| it.map(|it| it.path().is_dir()).unwrap_or(false)))
+        .unwrap_or(false);
+    if has_tools {
+        checks.push(Check::pass(
+            "android",
+            format!("NDK {} at {:?}", version, ndk),
+        ));
+    } else {
+        checks.push(
+            Check::fail("android", format!("no toolchain in {:?}", prebuilt))
+                .with_hint("the NDK installation looks incomplete, reinstall it"),
+        );
+    }
+}
+
+fn diagnose_adb_devices(adb: &path::Path, checks: &mut Vec<Check>) {
+    let output = match process::Command::new(adb)
+        .arg("devices")
+        .arg("-l")
+        .log_invocation(3)
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => {
+            checks.push(Check::fail(
+                "android",
+                format!("running adb devices: {}", e),
+            ));
+            return;
+        }
+    };
+    for entry in parse_adb_devices(&String::from_utf8_lossy(&output.stdout)) {
+        let subject = format!("android device {}", entry.serial);
+        checks.push(match entry.state.as_str() {
+            "device" => Check::pass(subject, "ready"),
+            "unauthorized" => Check::fail(subject, "unauthorized")
+                .with_hint("accept the USB debugging prompt on the device"),
+            "offline" => Check::fail(subject, "offline")
+                .with_hint("reconnect the device, or restart adb with `adb kill-server`"),
+            state if state.starts_with("no permissions") => {
+                Check::fail(subject, state).with_hint("check the udev rules for the device")
+            }
+            state => Check::warn(subject, format!("in {} state", state)),
+        });
+    }
+}
+
 fn probable_sdk_locs() -> Result<Vec<path::PathBuf>> {
     let mut v = vec![];
     for var in &[
@@ -193,7 +414,19 @@ fn probable_sdk_locs() -> Result<Vec<path::PathBuf>> {
     Ok(v)
 }
 
-fn ndk() -> Result<Option<path::PathBuf>> {
+/// The NDK from the configuration, the environment, or the SDK.
+fn ndk(conf: &AndroidConfiguration) -> Result<Option<path::PathBuf>> {
+    if let Some(path) = &conf.ndk_path {
+        return Ok(Some(path.into()));
+    }
+    if let Some(version) = &conf.ndk_version {
+        for sdk in probable_sdk_locs()? {
+            if let Some(ndk) = find_ndk_version(&sdk, version)? {
+                return Ok(Some(ndk));
+            }
+        }
+        bail!("NDK {} not found in the ndk directory of the SDK", version)
+    }
     if let Ok(path) = env::var("ANDROID_NDK_HOME") {
         return Ok(Some(path.into()));
     }
@@ -212,6 +445,17 @@ fn ndk() -> Result<Option<path::PathBuf>> {
     Ok(None)
 }
 
+/// An LLVM tool shipped with the NDK the android platforms use, like `llvm-profdata`.
+pub(crate) fn ndk_llvm_tool(conf: &AndroidConfiguration, name: &str) -> Option<path::PathBuf> {
+    let prebuilt = ndk(conf).ok()??.join("toolchains/llvm/prebuilt");
+    prebuilt
+        .read_dir()
+        .ok()?
+        .filter_map(|it| it.ok())
+        .map(|it| it.path().join("bin").join(name))
+        .find(|it| it.is_file())
+}
+
 fn ndk_version(ndk: &path::Path) -> Result<String> {
     let sources_prop_file = ndk.join("source.properties");
     let props = fs::read_to_string(&sources_prop_file)
@@ -257,6 +501,22 @@ fn adb() -> Result<path::PathBuf> {
 }
 
 fn find_non_legacy_ndk(sdk: &path::Path) -> Result<Option<path::PathBuf>> {
+    find_ndk_matching(sdk, |_| true)
+}
+
+/// The most recent NDK installed in the SDK with the version, or a version starting with it
+/// (`26` for `26.1.10909125`).
+fn find_ndk_version(sdk: &path::Path, version: &str) -> Result<Option<path::PathBuf>> {
+    find_ndk_matching(sdk, |it| {
+        let it = it.to_string();
+        it == version || it.starts_with(&format!("{}.", version))
+    })
+}
+
+fn find_ndk_matching(
+    sdk: &path::Path,
+    filter: impl Fn(&semver::Version) -> bool,
+) -> Result<Option<path::PathBuf>> {
     let ndk_root = sdk.join("ndk");
     if !ndk_root.is_dir() {
         return Ok(None);
@@ -274,6 +534,7 @@ fn find_non_legacy_ndk(sdk: &path::Path) -> Result<Option<path::PathBuf>> {
                 })
                 .map(|version| (directory, version))
         })
+        .filter(|(_, version)| filter(version))
         .max_by(|left, right| {
             let left_version: &semver::Version = &left.1;
             let right_version: &semver::Version = &right.1;
@@ -305,6 +566,87 @@ mod tests {
         assert_eq!(Some(expected), ndk);
     }
 
+    #[test]
+    fn test_parse_adb_devices() {
+        let output = "List of devices attached
+emulator-5554          device product:sdk_gphone64_x86_64 model:sdk_gphone64_x86_64 device:emu64xa transport_id:1
+R58M123ABC             unauthorized usb:1-1 transport_id:2
+0123456789ABCDEF       no permissions (missing udev rules? user is in the plugdev group); see [http://developer.android.com/tools/device.html] usb:1-2 transport_id:3
+
+";
+        let entries = parse_adb_devices(output);
+        assert_eq!(
+            entries,
+            vec![
+                AdbDeviceEntry {
+                    serial: "emulator-5554".to_string(),
+                    state: "device".to_string(),
+                    model: Some("sdk_gphone64_x86_64".to_string()),
+                    product: Some("sdk_gphone64_x86_64".to_string()),
+                    transport_id: Some("1".to_string()),
+                    emulator: true,
+                },
+                AdbDeviceEntry {
+                    serial: "R58M123ABC".to_string(),
+                    state: "unauthorized".to_string(),
+                    transport_id: Some("2".to_string()),
+                    ..AdbDeviceEntry::default()
+                },
+                AdbDeviceEntry {
+                    serial: "0123456789ABCDEF".to_string(),
+                    state: "no permissions (missing udev rules? user is in the plugdev group); see [http://developer.android.com/tools/device.html]".to_string(),
+                    transport_id: Some("3".to_string()),
+                    ..AdbDeviceEntry::default()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_ndk_version() {
+        let sdk_dir = tempfile::tempdir().unwrap();
+        let sdk = sdk_dir.path();
+        let ndk_root = sdk.join("ndk");
+        for version in &[
+            "25.2.9519653",
+            "26.1.10909125",
+            "26.3.11579264",
+            "27.0.12077973",
+        ] {
+            fs::create_dir_all(ndk_root.join(version)).unwrap();
+        }
+
+        assert_eq!(
+            Some(ndk_root.join("26.3.11579264")),
+            find_ndk_version(sdk, "26").unwrap()
+        );
+        assert_eq!(
+            Some(ndk_root.join("26.1.10909125")),
+            find_ndk_version(sdk, "26.1.10909125").unwrap()
+        );
+        assert_eq!(None, find_ndk_version(sdk, "2").unwrap());
+    }
+
+    #[test]
+    fn test_ndk_llvm_tool_of_configured_ndk() {
+        let ndk_dir = tempfile::tempdir().unwrap();
+        let bin = ndk_dir
+            .path()
+            .join("toolchains/llvm/prebuilt/linux-x86_64/bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join("llvm-profdata"), "").unwrap();
+        let conf = AndroidConfiguration {
+            ndk_path: Some(ndk_dir.path().to_string_lossy().to_string()),
+            ..AndroidConfiguration::default()
+        };
+
+        assert_eq!(
+            Some(bin.join("llvm-profdata")),
+            ndk_llvm_tool(&conf, "llvm-profdata")
+        );
+        assert_eq!(None, ndk_llvm_tool(&conf, "llvm-cov"));
+    }
+
     #[test]
     fn test_find_non_legacy_ndk_on_non_existing_directory() {
         let sdk = tempfile::tempdir().unwrap();
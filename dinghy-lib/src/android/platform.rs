@@ -127,4 +127,8 @@ impl Platform for AndroidPlatform {
     fn sysroot(&self) -> anyhow::Result<Option<PathBuf>> {
         self.regular_platform.sysroot()
     }
+
+    fn toolchain(&self) -> Option<PathBuf> {
+        self.regular_platform.toolchain()
+    }
 }
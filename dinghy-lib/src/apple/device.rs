@@ -9,6 +9,8 @@ use crate::Build;
 use crate::BuildBundle;
 use crate::Device;
 use crate::DeviceCompatibility;
+use crate::DeviceKind;
+use crate::RunOutcome;
 use crate::Runnable;
 use colored::Colorize;
 use fs_err as fs;
@@ -20,7 +22,7 @@ use std::fmt::Formatter;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 use std::process::{self, Stdio};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Debug)]
 pub struct IosDevice {
@@ -145,7 +147,7 @@ impl IosDevice {
         args: &[&str],
         envs: &[&str],
         debugger: bool,
-    ) -> Result<()> {
+    ) -> Result<process::ExitStatus> {
         if self.is_pre_ios_17()? {
             return self.run_remote_with_ios_deploy(build_bundle, args, envs, debugger);
         }
@@ -250,14 +252,10 @@ exit
             if line.contains("exited with status = ") {
                 let rv = line.split_whitespace().nth(6).unwrap();
                 println!("returns: {rv}");
-                if rv == "0" {
-                    return Ok(());
-                } else {
-                    bail!("Failed")
-                }
+                return Ok(exit_status(rv.parse()?));
             }
         }
-        Ok(())
+        bail!("Failed to get the exit status of the app from lldb")
     }
 
     // LEGACY IOS-DEPLOY BASED WORKFLOW (iOS<17)
@@ -277,7 +275,7 @@ exit
         args: &[&str],
         envs: &[&str],
         debugger: bool,
-    ) -> Result<()> {
+    ) -> Result<process::ExitStatus> {
         let bundle = build_bundle.bundle_dir.to_string_lossy();
         let mut command = process::Command::new("ios-deploy");
         command.args(&["-i", &self.id, "-b", &bundle, "-m"]);
@@ -291,10 +289,7 @@ exit
             .output()
             .context("Failed to run ios-deploy")?
             .status;
-        if !status.success() {
-            bail!("Run on device failed")
-        }
-        Ok(())
+        Ok(status)
     }
 }
 
@@ -320,7 +315,9 @@ impl Device for IosDevice {
                 0,
             );
         }
-        self.run_remote(&build_bundle, args, envs, true)?;
+        if !self.run_remote(&build_bundle, args, envs, true)?.success() {
+            bail!("Run on device failed")
+        }
         Ok(build_bundle)
     }
 
@@ -328,6 +325,10 @@ impl Device for IosDevice {
         &self.id
     }
 
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Apple
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
@@ -338,8 +339,12 @@ impl Device for IosDevice {
         build: &Build,
         args: &[&str],
         envs: &[&str],
-    ) -> Result<BuildBundle> {
+    ) -> Result<RunOutcome> {
+        if !build.launcher.is_empty() {
+            bail!("Launchers are not supported on Apple devices")
+        }
         let build_bundle = self.install_app(project, build, &build.runnable)?;
+        let started = Instant::now();
         if get_current_verbosity() < 1 {
             // we log the full command for verbosity > 1, just log a short message when the user
             // didn't ask for verbose output
@@ -349,8 +354,8 @@ impl Device for IosDevice {
                 0,
             );
         }
-        self.run_remote(&build_bundle, args, envs, false)?;
-        Ok(build_bundle)
+        let status = self.run_remote(&build_bundle, args, envs, false)?;
+        Ok(RunOutcome::from_status(build_bundle, status, started))
     }
 }
 
@@ -442,6 +447,10 @@ impl Device for AppleSimDevice {
         &self.id
     }
 
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Apple
+    }
+
     fn name(&self) -> &str {
         &self.name
     }
@@ -452,8 +461,12 @@ impl Device for AppleSimDevice {
         build: &Build,
         args: &[&str],
         envs: &[&str],
-    ) -> Result<BuildBundle> {
+    ) -> Result<RunOutcome> {
+        if !build.launcher.is_empty() {
+            bail!("Launchers are not supported on Apple devices")
+        }
         let build_bundle = self.install_app(&project, &build, &build.runnable)?;
+        let started = Instant::now();
         if get_current_verbosity() < 1 {
             // we log the full command for verbosity > 1, just log a short message when the user
             // didn't ask for verbose output
@@ -463,8 +476,8 @@ impl Device for AppleSimDevice {
                 0,
             );
         }
-        launch_app(&self, args, envs)?;
-        Ok(build_bundle)
+        let status = launch_app(&self, args, envs)?;
+        Ok(RunOutcome::from_status(build_bundle, status, started))
     }
 }
 
@@ -535,7 +548,11 @@ fn make_apple_app(
     Ok(build_bundle)
 }
 
-fn launch_app(dev: &AppleSimDevice, app_args: &[&str], _envs: &[&str]) -> Result<()> {
+fn launch_app(
+    dev: &AppleSimDevice,
+    app_args: &[&str],
+    _envs: &[&str],
+) -> Result<process::ExitStatus> {
     use std::io::Write;
     let dir = tempfile::TempDir::with_prefix("mobiledevice-rs-lldb")?;
     let tmppath = dir.path();
@@ -616,13 +633,8 @@ fn launch_app(dev: &AppleSimDevice, app_args: &[&str], _envs: &[&str]) -> Result
         .find(|line| line.contains("exited with status"));
     if let Some(exit_status_line) = exit_status_line {
         let words: Vec<&str> = exit_status_line.split_whitespace().rev().collect();
-        if let Some(exit_status) = words.get(1) {
-            let exit_status = exit_status.parse::<u32>()?;
-            if exit_status == 0 {
-                Ok(())
-            } else {
-                bail!("Test failure, exit code: {}", exit_status)
-            }
+        if let Some(code) = words.get(1) {
+            Ok(exit_status(code.parse()?))
         } else {
             panic!(
                 "Failed to parse lldb exit line for an exit status. {:?}",
@@ -634,6 +646,12 @@ fn launch_app(dev: &AppleSimDevice, app_args: &[&str], _envs: &[&str]) -> Result
     }
 }
 
+/// The status of a process which exited with the code reported by lldb.
+fn exit_status(code: i32) -> process::ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    process::ExitStatus::from_raw((code & 0xff) << 8)
+}
+
 fn launch_lldb_simulator(
     dev: &AppleSimDevice,
     installed: &str,
@@ -100,6 +100,10 @@ impl Platform for AppleDevicePlatform {
     fn sysroot(&self) -> Result<Option<std::path::PathBuf>> {
         self.sysroot_path().map(|s| Some(s.into()))
     }
+
+    fn toolchain(&self) -> Option<std::path::PathBuf> {
+        None
+    }
 }
 
 impl Display for AppleDevicePlatform {
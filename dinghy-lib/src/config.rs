@@ -84,6 +84,9 @@ pub struct Configuration {
     pub script_devices: collections::BTreeMap<String, ScriptDeviceConfiguration>,
     pub test_data: Vec<TestData>,
     pub skip_source_copy: bool,
+    /// Globs of the files to copy back from the bundle directory on the device after a run
+    pub collect: Vec<String>,
+    pub android: AndroidConfiguration,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -93,6 +96,8 @@ struct ConfigurationFileContent {
     pub script_devices: Option<collections::BTreeMap<String, ScriptDeviceConfiguration>>,
     pub test_data: Option<collections::BTreeMap<String, TestDataConfiguration>>,
     pub skip_source_copy: Option<bool>,
+    pub collect: Option<Vec<String>>,
+    pub android: Option<AndroidConfiguration>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -103,6 +108,11 @@ pub struct PlatformConfiguration {
     pub rustc_triple: Option<String>,
     pub sysroot: Option<String>,
     pub toolchain: Option<String>,
+    /// Seconds after which a run on the devices of this platform is killed
+    pub timeout: Option<u64>,
+    /// Command the executables are run through on the devices of this platform, like
+    /// `["taskset", "-c", "2"]`
+    pub launcher: Option<Vec<String>>,
 }
 
 impl PlatformConfiguration {
@@ -114,9 +124,19 @@ impl PlatformConfiguration {
             rustc_triple: None,
             sysroot: None,
             toolchain: None,
+            timeout: None,
+            launcher: None,
         }
     }
 
+    /// The configured toolchain, or `~/.dinghy/toolchain/<platform>` when there is none.
+    pub fn toolchain_path(&self, platform_name: &str) -> Option<path::PathBuf> {
+        self.toolchain
+            .clone()
+            .map(path::PathBuf::from)
+            .or(dirs::home_dir().map(|it| it.join(".dinghy").join("toolchain").join(platform_name)))
+    }
+
     pub fn env(&self) -> Vec<(String, String)> {
         self.env
             .as_ref()
@@ -129,6 +149,20 @@ impl PlatformConfiguration {
     }
 }
 
+/// How the `auto-android-*` platforms are generated from the NDK.
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct AndroidConfiguration {
+    /// The NDK to use, instead of the one found from the environment and the SDK
+    pub ndk_path: Option<String>,
+    /// Version of the NDK to use among the ones installed side by side in the `ndk` directory
+    /// of the SDK, like `26` or `26.1.10909125`
+    pub ndk_version: Option<String>,
+    /// API level of the `auto-android-<cpu>` platforms, 21 when not set
+    pub api_level: Option<u32>,
+    /// ABIs to generate platforms for, like `["arm64-v8a", "x86_64"]`, all of them when not set
+    pub abis: Option<Vec<String>>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct OverlayConfiguration {
     pub path: String,
@@ -137,7 +171,11 @@ pub struct OverlayConfiguration {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct SshDeviceConfiguration {
+    /// May be left out when `ssh_config_host` is set
+    #[serde(default)]
     pub hostname: String,
+    /// May be left out to use the user from the ssh configuration
+    #[serde(default)]
     pub username: String,
     pub port: Option<u16>,
     pub path: Option<String>,
@@ -148,15 +186,118 @@ pub struct SshDeviceConfiguration {
     pub remote_shell_vars: collections::HashMap<String, String>,
     pub install_adhoc_rsync_local_path: Option<String>,
     pub use_legacy_scp_protocol_for_adhoc_rsync_copy: Option<bool>,
+    /// Seconds after which a run on this device is killed
+    pub timeout: Option<u64>,
+    /// Command the executables are run through on the device, like `["taskset", "-c", "2"]`
+    pub launcher: Option<Vec<String>>,
+    /// Share one ssh connection between the commands run on the device (defaults to true)
+    pub connection_sharing: Option<bool>,
+    /// How files are copied to the device
+    #[serde(default)]
+    pub transfer: SshTransfer,
+    /// Private key to authenticate with
+    pub identity_file: Option<String>,
+    /// Bastion(s) to connect through, as for `ssh -J`
+    pub proxy_jump: Option<String>,
+    /// Extra options like `StrictHostKeyChecking=no`, as for `ssh -o`
+    #[serde(default)]
+    pub ssh_options: Vec<String>,
+    /// Host alias from `~/.ssh/config` to connect to, instead of `hostname`
+    pub ssh_config_host: Option<String>,
+    /// Debugger to use with `--debugger`, detected from what the device has when not set
+    pub debugger: Option<SshDebugger>,
+    /// Find the platforms of the device from its architecture and libc when `platform` is not
+    /// set, instead of assuming it is the host one
+    #[serde(default)]
+    pub detect_platform: bool,
+}
+
+impl SshDeviceConfiguration {
+    /// The platform a device with a `target` (and usually a `toolchain`) but no `platform`
+    /// defines, named after the device.
+    pub fn implicit_platform(&self) -> Option<PlatformConfiguration> {
+        if self.platform.is_some() {
+            return None;
+        }
+        self.target.as_ref().map(|target| PlatformConfiguration {
+            rustc_triple: Some(target.clone()),
+            toolchain: self.toolchain.clone(),
+            timeout: self.timeout,
+            launcher: self.launcher.clone(),
+            ..PlatformConfiguration::empty()
+        })
+    }
+}
+
+/// Debug server started on an ssh device, and the matching debugger run on the host.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SshDebugger {
+    /// gdbserver, with gdb-multiarch (or gdb) on the host
+    Gdb,
+    /// lldb-server, with lldb on the host
+    Lldb,
+}
+
+/// How files are copied to an ssh device. Without rsync, the files whose hash matches the one
+/// of the file already on the device are skipped.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SshTransfer {
+    #[default]
+    Rsync,
+    /// A tar archive piped through ssh
+    Tar,
+    Sftp,
+    Scp,
+}
+
+impl fmt::Display for SshTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SshTransfer::Rsync => write!(f, "rsync"),
+            SshTransfer::Tar => write!(f, "tar"),
+            SshTransfer::Sftp => write!(f, "sftp"),
+            SshTransfer::Scp => write!(f, "scp"),
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ScriptDeviceConfiguration {
     pub path: String,
     pub platform: Option<String>,
+    /// Seconds after which a run on this device is killed
+    pub timeout: Option<u64>,
+    /// Command the script gets in `DINGHY_LAUNCHER` to run the executable through
+    pub launcher: Option<Vec<String>>,
 }
 
 impl Configuration {
+    /// The configuration of a platform, including the ones ssh devices define with their
+    /// `target`. The API level variants of the android auto platforms, like
+    /// `auto-android-aarch64-api30`, fall back to the one of their architecture
+    /// (`auto-android-aarch64`).
+    pub fn platform_configuration(&self, platform_id: &str) -> Option<PlatformConfiguration> {
+        if let Some(platform) = self.platforms.get(platform_id) {
+            return Some(platform.clone());
+        }
+        if let Some(platform) = self
+            .ssh_devices
+            .get(platform_id)
+            .and_then(|it| it.implicit_platform())
+        {
+            return Some(platform);
+        }
+        let cpu = platform_id
+            .strip_prefix("auto-android-")?
+            .split('-')
+            .next()?;
+        self.platforms
+            .get(&format!("auto-android-{}", cpu))
+            .cloned()
+    }
+
     pub fn merge(&mut self, file: &path::Path) -> Result<()> {
         let other = read_config_file(&file)?;
         if let Some(pfs) = other.platforms {
@@ -179,6 +320,13 @@ impl Configuration {
         if let Some(skip_source_copy) = other.skip_source_copy {
             self.skip_source_copy = skip_source_copy
         }
+        self.collect.extend(other.collect.unwrap_or_default());
+        if let Some(android) = other.android {
+            self.android.ndk_path = android.ndk_path.or(self.android.ndk_path.take());
+            self.android.ndk_version = android.ndk_version.or(self.android.ndk_version.take());
+            self.android.api_level = android.api_level.or(self.android.api_level);
+            self.android.abis = android.abis.or(self.android.abis.take());
+        }
         Ok(())
     }
 }
@@ -236,4 +384,30 @@ mod tests {
             .join("../../../test-ws/test-app/.dinghy.toml");
         super::read_config_file(config_file).unwrap();
     }
+
+    #[test]
+    fn test_platform_configuration_of_ssh_device_target() {
+        let mut conf = super::Configuration::default();
+        conf.ssh_devices.insert(
+            "board".to_string(),
+            toml::from_str(
+                "hostname = \"board\"\ntarget = \"aarch64-unknown-linux-gnu\"\ntimeout = 30\nlauncher = [\"taskset\", \"-c\", \"2\"]",
+            )
+            .unwrap(),
+        );
+        let platform = conf.platform_configuration("board").unwrap();
+        assert_eq!(
+            platform.rustc_triple.as_deref(),
+            Some("aarch64-unknown-linux-gnu")
+        );
+        assert_eq!(platform.timeout, Some(30));
+        assert_eq!(
+            platform.launcher,
+            Some(vec![
+                "taskset".to_string(),
+                "-c".to_string(),
+                "2".to_string()
+            ])
+        );
+    }
 }
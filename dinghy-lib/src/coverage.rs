@@ -0,0 +1,112 @@
+use crate::config::AndroidConfiguration;
+use crate::errors::*;
+use crate::utils::LogCommandExt;
+use dinghy_build::build_env::envify;
+use fs_err as fs;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use walkdir::WalkDir;
+
+/// Makes cargo instrument the code built for `rustc_triple`.
+///
+/// `RUSTFLAGS` takes precedence over the per-target flags in cargo, so the flag goes there when
+/// it is set.
+pub fn instrument(cargo: &mut Command, rustc_triple: &str) {
+    let flag = "-C instrument-coverage";
+    let var = match env::var("RUSTFLAGS") {
+        Ok(_) => "RUSTFLAGS".to_string(),
+        Err(_) => format!("CARGO_TARGET_{}_RUSTFLAGS", envify(rustc_triple)),
+    };
+    let flags = match env::var(&var) {
+        Ok(flags) if !flags.trim().is_empty() => format!("{} {}", flags, flag),
+        _ => flag.to_string(),
+    };
+    cargo.env(var, flags);
+}
+
+/// `LLVM_PROFILE_FILE` for a run, relative to the bundle directory it runs in. The stamp tells
+/// the profiles of this run from the ones previous runs left on the device.
+pub fn profile_file(stamp: u128) -> String {
+    format!("dinghy-{}-%p-%m.profraw", stamp)
+}
+
+/// Glob matching the profiles written with [`profile_file`].
+pub fn profile_glob(stamp: u128) -> String {
+    format!("dinghy-{}-*.profraw", stamp)
+}
+
+/// Where the profiles collected from the devices of a platform are gathered until they get
+/// merged.
+pub fn profiles_dir(target_path: &Path, platform_id: &str) -> PathBuf {
+    target_path
+        .join("dinghy")
+        .join("coverage")
+        .join(platform_id)
+}
+
+/// The profile data merged from every run on the devices of a platform.
+pub fn profdata_path(target_path: &Path, platform_id: &str) -> PathBuf {
+    target_path
+        .join("dinghy")
+        .join("coverage")
+        .join(format!("{}.profdata", platform_id))
+}
+
+/// Merges the collected profiles of a platform with `llvm-profdata`, and returns the path of
+/// the result.
+pub fn merge(
+    target_path: &Path,
+    platform_id: &str,
+    android: &AndroidConfiguration,
+) -> Result<PathBuf> {
+    let profiles = WalkDir::new(profiles_dir(target_path, platform_id))
+        .into_iter()
+        .filter_map(|it| it.ok())
+        .filter(|it| it.path().extension().is_some_and(|ext| ext == "profraw"))
+        .map(|it| it.into_path())
+        .collect::<Vec<_>>();
+    if profiles.is_empty() {
+        bail!(
+            "No coverage profile was collected from the devices of platform {}",
+            platform_id
+        )
+    }
+    let profdata = profdata_path(target_path, platform_id);
+    let status = Command::new(llvm_profdata(android)?)
+        .arg("merge")
+        .arg("-sparse")
+        .args(&profiles)
+        .arg("-o")
+        .arg(&profdata)
+        .stdin(Stdio::null())
+        .log_invocation(1)
+        .status()?;
+    if !status.success() {
+        bail!("llvm-profdata could not merge the coverage profiles")
+    }
+    Ok(profdata)
+}
+
+/// `llvm-profdata` from the llvm-tools rustup component, which matches the LLVM of rustc, or
+/// from the PATH or the NDK.
+fn llvm_profdata(android: &AndroidConfiguration) -> Result<PathBuf> {
+    let output = Command::new("rustc")
+        .arg("--print")
+        .arg("sysroot")
+        .log_invocation(3)
+        .output()?;
+    let sysroot = PathBuf::from(String::from_utf8(output.stdout)?.trim());
+    let rustup_tool = fs::read_dir(sysroot.join("lib").join("rustlib"))
+        .into_iter()
+        .flatten()
+        .filter_map(|it| it.ok())
+        .map(|it| it.path().join("bin").join("llvm-profdata"))
+        .find(|it| it.is_file());
+    rustup_tool
+        .or_else(|| which::which("llvm-profdata").ok())
+        .or_else(|| crate::android::ndk_llvm_tool(android, "llvm-profdata"))
+        .ok_or_else(|| {
+            anyhow!("llvm-profdata not found, install it with `rustup component add llvm-tools`")
+        })
+}
@@ -5,13 +5,97 @@ use crate::utils::copy_and_sync_file;
 use crate::Build;
 use crate::BuildBundle;
 use fs_err as fs;
+use globset::{Glob, GlobSetBuilder};
 use log::debug;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub fn make_remote_app(project: &Project, build: &Build) -> Result<BuildBundle> {
     make_remote_app_with_name(project, build, None)
 }
 
+/// The bundle `make_remote_app` makes for a build, without creating anything. Useful to cleanup
+/// when `run_app` failed before returning its bundle.
+pub fn bundle_for(build: &Build) -> BuildBundle {
+    bundle_with_name_for(build, None)
+}
+
+fn bundle_with_name_for(build: &Build, bundle_name: Option<&str>) -> BuildBundle {
+    let mut root_dir = build
+        .target_path
+        .join("dinghy")
+        .join(build.runnable.id.clone());
+    if let [device_id] = &build.setup_args.device_ids[..] {
+        // the same runnable may be bundled for several devices at the same time
+        root_dir = root_dir.join(device_id);
+    }
+    let bundle_dir = match bundle_name {
+        Some(name) => root_dir.join(&build.runnable.package_name).join(name),
+        None => root_dir.join(&build.runnable.package_name),
+    };
+    BuildBundle {
+        id: build.runnable.id.clone(),
+        bundle_exe: bundle_dir.join(format!("_dinghy_{}", &build.runnable.id)),
+        bundle_dir,
+        lib_dir: root_dir.join("overlay"),
+        root_dir,
+        app_id: None,
+    }
+}
+
+/// Shell prefix recording the pid of the program it runs in the current directory (the remote
+/// bundle directory) before exec-ing it, so that [`remote_kill_command`] can kill it.
+pub(crate) const REMOTE_PID_RECORDER: &str = "sh -c 'echo $$ > dinghy.pid; exec \"$0\" \"$@\"'";
+
+/// Shell command killing the program started with [`REMOTE_PID_RECORDER`] in `bundle_dir`,
+/// forcefully if it is still alive a second later.
+pub(crate) fn remote_kill_command(bundle_dir: &str) -> String {
+    format!(
+        "pid=$(cat '{}/dinghy.pid' 2>/dev/null) && kill -TERM $pid 2>/dev/null && sleep 1 && kill -KILL $pid 2>/dev/null ; true",
+        bundle_dir
+    )
+}
+
+/// Where the files collected from `device_id` after running a build are copied.
+pub fn collected_dir(build: &Build, device_id: &str) -> PathBuf {
+    build
+        .target_path
+        .join("dinghy")
+        .join(&build.runnable.id)
+        .join("collected")
+        .join(device_id)
+}
+
+/// Shell command listing the files of `bundle_dir` on a device, for [`files_to_collect`].
+pub(crate) fn remote_find_command(bundle_dir: &str) -> String {
+    format!("cd '{}' && find . -type f", bundle_dir)
+}
+
+/// The files of a `find . -type f` listing matching the `collect` globs.
+pub(crate) fn files_to_collect(find_output: &str, patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut globs = GlobSetBuilder::new();
+    for pattern in patterns {
+        globs.add(Glob::new(pattern).with_context(|| format!("Invalid collect glob {}", pattern))?);
+    }
+    let globs = globs.build()?;
+    Ok(find_output
+        .lines()
+        .map(|it| it.trim_end_matches('\r'))
+        .map(|it| it.strip_prefix("./").unwrap_or(it))
+        .filter(|it| globs.is_match(it))
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// The launcher of a build, escaped for a shell command line.
+pub(crate) fn shell_launcher(build: &Build) -> String {
+    build
+        .launcher
+        .iter()
+        .map(|it| shell_escape::escape(it.into()).to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub fn make_remote_app_with_name(
     project: &Project,
     build: &Build,
@@ -30,14 +114,12 @@ pub fn make_remote_app_with_name(
                 && !path.to_str().unwrap().contains("android"))
     }
 
-    let root_dir = build.target_path.join("dinghy").join(build.runnable.id.clone());
-    let bundle_path = match bundle_name {
-        Some(name) => root_dir.join(&build.runnable.package_name).join(name),
-        None => root_dir.join(&build.runnable.package_name),
-    };
-    let bundle_libs_path = root_dir.join("overlay");
+    let bundle = bundle_with_name_for(build, bundle_name);
+    let root_dir = bundle.root_dir.clone();
+    let bundle_path = bundle.bundle_dir.clone();
+    let bundle_libs_path = bundle.lib_dir.clone();
     let bundle_target_path = &bundle_path;
-    let bundle_exe_path = bundle_target_path.join(format!("_dinghy_{}", &build.runnable.id));
+    let bundle_exe_path = bundle.bundle_exe.clone();
 
     debug!("Removing previous bundle {:?}", bundle_path);
     let _ = fs::remove_dir_all(&bundle_path);
@@ -127,12 +209,26 @@ pub fn make_remote_app_with_name(
     debug!("Copying test_data to bundle {}", bundle_path.display());
     project.copy_test_data(&bundle_path)?;
 
-    Ok(BuildBundle {
-        id: build.runnable.id.clone(),
-        bundle_dir: bundle_path.to_path_buf(),
-        bundle_exe: bundle_exe_path.to_path_buf(),
-        lib_dir: bundle_libs_path.to_path_buf(),
-        root_dir,
-        app_id: None,
-    })
+    Ok(bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::files_to_collect;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_files_to_collect() {
+        let listing = "./dinghy.pid\r\n./default.profraw\r\n./criterion/a/new/estimates.json\r\n./src/lib.rs\r\n./out/deep.profraw\r\n";
+        let patterns = vec!["criterion/**".to_string(), "*.profraw".to_string()];
+        assert_eq!(
+            files_to_collect(listing, &patterns).unwrap(),
+            vec![
+                PathBuf::from("default.profraw"),
+                PathBuf::from("criterion/a/new/estimates.json"),
+                PathBuf::from("out/deep.profraw"),
+            ]
+        );
+        assert!(files_to_collect(listing, &["[".to_string()]).is_err());
+    }
 }
@@ -0,0 +1,301 @@
+//! Environment diagnostics for `cargo dinghy doctor`.
+//!
+//! Most of the probing done by [`Dinghy::probe`](crate::Dinghy::probe) silently skips what it
+//! can not use. The doctor runs the same discovery, but reports every problem it finds along with
+//! a hint to fix it.
+
+use crate::config::{PlatformConfiguration, ScriptDeviceConfiguration, SshTransfer};
+use crate::platform::regular_platform::RegularPlatform;
+use crate::ssh::SshDevice;
+use crate::utils::LogCommandExt;
+use crate::Configuration;
+use colored::Colorize;
+use std::fmt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Pass => write!(f, "{}", "pass".green().bold()),
+            Status::Warn => write!(f, "{}", "warn".yellow().bold()),
+            Status::Fail => write!(f, "{}", "fail".red().bold()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Check {
+    pub status: Status,
+    /// What was checked, like `platform aarch64-linux` or `ssh device pi`
+    pub subject: String,
+    pub message: String,
+    /// How to fix the problem, for warnings and failures
+    pub hint: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn pass(subject: impl Into<String>, message: impl Into<String>) -> Check {
+        Check::new(Status::Pass, subject, message)
+    }
+
+    pub(crate) fn warn(subject: impl Into<String>, message: impl Into<String>) -> Check {
+        Check::new(Status::Warn, subject, message)
+    }
+
+    pub(crate) fn fail(subject: impl Into<String>, message: impl Into<String>) -> Check {
+        Check::new(Status::Fail, subject, message)
+    }
+
+    fn new(status: Status, subject: impl Into<String>, message: impl Into<String>) -> Check {
+        Check {
+            status,
+            subject: subject.into(),
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub(crate) fn with_hint(mut self, hint: impl Into<String>) -> Check {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.subject, self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n       {} {}", "hint:".bold(), hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the android tooling, every configured platform and device, and the plugins.
+pub fn diagnose(conf: &Configuration) -> Vec<Check> {
+    let mut checks = vec![];
+    crate::android::diagnose(conf, &mut checks);
+    for (name, platform) in &conf.platforms {
+        if name != "host" && !name.starts_with("auto-android-") {
+            diagnose_platform(name, platform, &mut checks);
+        }
+    }
+    let uses_rsync = conf
+        .ssh_devices
+        .values()
+        .any(|it| it.transfer == SshTransfer::Rsync);
+    if uses_rsync && which::which("rsync").is_err() {
+        checks.push(
+            Check::fail(
+                "ssh",
+                "rsync not found, it is needed to install apps on ssh devices",
+            )
+            .with_hint("install rsync on this machine"),
+        );
+    }
+    for (id, ssh) in &conf.ssh_devices {
+        let subject = format!("ssh device {}", id);
+        diagnose_platform_reference(&subject, ssh.platform.as_deref(), conf, &mut checks);
+        if let Some(platform) = ssh.implicit_platform() {
+            diagnose_platform(id, &platform, &mut checks);
+        }
+        let device = SshDevice::new(id.clone(), ssh.clone(), None);
+        device.diagnose(&mut checks);
+    }
+    for (id, script) in &conf.script_devices {
+        diagnose_script_device(id, script, conf, &mut checks);
+    }
+    for plugin in crate::plugin::auto_detect_plugins() {
+        checks.push(Check::pass(
+            format!("plugin {}", plugin),
+            "found in PATH, its devices and platforms will be loaded",
+        ));
+    }
+    checks
+}
+
+fn diagnose_platform(name: &str, conf: &PlatformConfiguration, checks: &mut Vec<Check>) {
+    let subject = format!("platform {}", name);
+    let Some(rustc_triple) = conf.rustc_triple.as_ref() else {
+        checks.push(
+            Check::fail(&subject, "no rustc_triple configured")
+                .with_hint("add a rustc_triple to the platform configuration"),
+        );
+        return;
+    };
+    let Some(toolchain_path) = conf.toolchain_path(name) else {
+        checks.push(
+            Check::fail(&subject, "no toolchain configured")
+                .with_hint("add a toolchain to the platform configuration"),
+        );
+        return;
+    };
+    let toolchain =
+        match RegularPlatform::toolchain_config(conf, rustc_triple.clone(), &toolchain_path) {
+            Ok(toolchain) => toolchain,
+            Err(e) => {
+                checks.push(
+                    Check::fail(
+                        &subject,
+                        format!("toolchain {}: {}", toolchain_path.display(), e),
+                    )
+                    .with_hint("a toolchain is a directory with a bin/<prefix>-gcc compiler"),
+                );
+                return;
+            }
+        };
+
+    let cc = toolchain.cc_executable(&toolchain.cc);
+    if Path::new(&cc).exists() {
+        checks.push(Check::pass(&subject, format!("compiler {}", cc)));
+    } else {
+        checks.push(
+            Check::fail(&subject, format!("compiler {} not found", cc))
+                .with_hint("check the toolchain installation"),
+        );
+    }
+    for tool in ["ar", "strip"] {
+        let exe = toolchain.binutils_executable(tool);
+        if !Path::new(&exe).exists() {
+            checks.push(
+                Check::warn(&subject, format!("{} not found", exe))
+                    .with_hint("check the toolchain binutils installation"),
+            );
+        }
+    }
+    let cxx = toolchain.cc_executable(&toolchain.cxx);
+    if !Path::new(&cxx).exists() {
+        checks.push(Check::warn(
+            &subject,
+            format!("{} not found, crates compiling C++ code will fail", cxx),
+        ));
+    }
+
+    match &toolchain.sysroot {
+        Some(sysroot) if sysroot.is_dir() => checks.push(Check::pass(
+            &subject,
+            format!("sysroot {}", sysroot.display()),
+        )),
+        Some(sysroot) => checks.push(
+            Check::fail(
+                &subject,
+                format!("sysroot {} does not exist", sysroot.display()),
+            )
+            .with_hint("check the toolchain installation"),
+        ),
+        None => checks.push(
+            Check::warn(&subject, "no sysroot found in toolchain")
+                .with_hint("crates linking to system libraries will probably fail to build"),
+        ),
+    }
+
+    checks.push(diagnose_rust_std(&subject, rustc_triple));
+}
+
+/// Checks the standard library for the target is installed in the current rust toolchain.
+fn diagnose_rust_std(subject: &str, rustc_triple: &str) -> Check {
+    let libdir = Command::new("rustc")
+        .arg("--print")
+        .arg("target-libdir")
+        .arg("--target")
+        .arg(rustc_triple)
+        .stderr(Stdio::null())
+        .log_invocation(3)
+        .output();
+    let libdir = match libdir {
+        Ok(output) if output.status.success() => {
+            PathBuf::from(String::from_utf8_lossy(&output.stdout).trim())
+        }
+        _ => {
+            return Check::warn(
+                subject,
+                format!("rustc does not know the {} target", rustc_triple),
+            )
+            .with_hint("custom targets need a nightly toolchain and -Z build-std")
+        }
+    };
+    let has_std = libdir
+        .read_dir()
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .any(|entry| entry.file_name().to_string_lossy().starts_with("libstd-"))
+        })
+        .unwrap_or(false);
+    if has_std {
+        Check::pass(subject, format!("rust std for {} installed", rustc_triple))
+    } else {
+        Check::fail(
+            subject,
+            format!("rust std for {} is not installed", rustc_triple),
+        )
+        .with_hint(format!("run `rustup target add {}`", rustc_triple))
+    }
+}
+
+fn diagnose_platform_reference(
+    subject: &str,
+    platform: Option<&str>,
+    conf: &Configuration,
+    checks: &mut Vec<Check>,
+) {
+    if let Some(platform) = platform {
+        if !is_known_platform(platform, conf) {
+            checks.push(
+                Check::fail(subject, format!("unknown platform {}", platform))
+                    .with_hint("declare the platform in a [platforms] section"),
+            );
+        }
+    }
+}
+
+/// Whether the platform is configured, or registered by dinghy itself: the host, the
+/// `auto-android-*`, `auto-ios-*`... platforms, and those defined by the target of ssh devices.
+fn is_known_platform(platform: &str, conf: &Configuration) -> bool {
+    platform == "host"
+        || platform.starts_with("auto-")
+        || conf.platforms.contains_key(platform)
+        || conf
+            .ssh_devices
+            .get(platform)
+            .is_some_and(|it| it.implicit_platform().is_some())
+}
+
+fn diagnose_script_device(
+    id: &str,
+    script: &ScriptDeviceConfiguration,
+    conf: &Configuration,
+    checks: &mut Vec<Check>,
+) {
+    let subject = format!("script device {}", id);
+    diagnose_platform_reference(&subject, script.platform.as_deref(), conf, checks);
+    let path = Path::new(&script.path);
+    if !path.is_file() {
+        checks.push(
+            Check::fail(&subject, format!("{} not found", script.path))
+                .with_hint("check the path of the script device"),
+        );
+        return;
+    }
+    let executable = path
+        .metadata()
+        .map(|m| m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false);
+    if !executable {
+        checks.push(
+            Check::fail(&subject, format!("{} is not executable", script.path))
+                .with_hint(format!("run `chmod +x {}`", script.path)),
+        );
+        return;
+    }
+    checks.push(Check::pass(&subject, format!("script {}", script.path)));
+}
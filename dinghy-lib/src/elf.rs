@@ -0,0 +1,386 @@
+use crate::errors::*;
+use fs_err as fs;
+use goblin::elf::Elf;
+use log::debug;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Finds the shared libraries an executable needs that are not provided by the target system.
+///
+/// The `DT_NEEDED` entries of the executable, and then of each library found, are looked up
+/// in the `DT_RUNPATH` / `DT_RPATH` of the object requiring them, next to the executable,
+/// in the `search_dirs` (the platform overlays) and in the sysroot. Libraries found in the
+/// sysroot are expected to be on the device and are left out, as well as libraries not found
+/// at all.
+///
+/// Returns an empty list for executables which are not ELF files.
+pub fn dynamic_libraries(
+    exe: &Path,
+    search_dirs: &[PathBuf],
+    sysroot: Option<&Path>,
+) -> Result<Vec<PathBuf>> {
+    let Some(needed) = needed_libraries(exe)? else {
+        debug!("{} is not an ELF file", exe.display());
+        return Ok(vec![]);
+    };
+    LibrarySearch::new(exe, search_dirs, sysroot).resolve(needed, needed_libraries)
+}
+
+/// Where the libraries are looked up, besides the runpaths of the objects needing them.
+struct LibrarySearch {
+    /// The directory of the executable and the `deps` or parent directory next to it
+    local_dirs: Vec<PathBuf>,
+    overlay_libs: HashMap<String, Vec<PathBuf>>,
+    sysroot_lib_dirs: Vec<PathBuf>,
+    sysroot_libs: HashMap<String, Vec<PathBuf>>,
+    /// `e_machine` of the executable, the libraries built for another one are not candidates
+    machine: Option<u16>,
+}
+
+impl LibrarySearch {
+    fn new(exe: &Path, search_dirs: &[PathBuf], sysroot: Option<&Path>) -> LibrarySearch {
+        let mut local_dirs = vec![];
+        if let Some(exe_dir) = exe.parent() {
+            local_dirs.push(exe_dir.to_path_buf());
+            if exe_dir.ends_with("deps") {
+                local_dirs.extend(exe_dir.parent().map(Path::to_path_buf));
+            } else {
+                local_dirs.push(exe_dir.join("deps"));
+            }
+        }
+        let sysroot_lib_dirs = sysroot
+            .map(|sysroot| {
+                ["lib", "lib64", "usr/lib", "usr/lib64"]
+                    .iter()
+                    .map(|it| sysroot.join(it))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        LibrarySearch {
+            local_dirs,
+            overlay_libs: index_libraries(search_dirs, usize::MAX),
+            sysroot_libs: index_libraries(&sysroot_lib_dirs, 2),
+            sysroot_lib_dirs,
+            machine: elf_machine(exe),
+        }
+    }
+
+    /// Whether a library can be loaded by the executable, which is assumed when either one is
+    /// not an ELF file.
+    fn runs_with_exe(&self, library: &Path) -> bool {
+        match (self.machine, elf_machine(library)) {
+            (Some(exe), Some(library)) => exe == library,
+            _ => true,
+        }
+    }
+
+    /// The first library of the index with this name the executable can load. The sysroot of
+    /// the NDK has one per ABI, for instance.
+    fn candidate(&self, index: &HashMap<String, Vec<PathBuf>>, name: &str) -> Option<PathBuf> {
+        index
+            .get(name)?
+            .iter()
+            .find(|it| self.runs_with_exe(it))
+            .cloned()
+    }
+
+    /// Walks the dependencies from the `needed` entries of the executable, reading the entries
+    /// of each library found with `needed_of`.
+    fn resolve(
+        &self,
+        needed: Needed,
+        needed_of: impl Fn(&Path) -> Result<Option<Needed>>,
+    ) -> Result<Vec<PathBuf>> {
+        let mut libraries = vec![];
+        let mut seen = HashSet::new();
+        let Needed { names, runpaths } = needed;
+        let mut queue: VecDeque<(String, Vec<PathBuf>)> = names
+            .into_iter()
+            .map(|name| (name, runpaths.clone()))
+            .collect();
+        while let Some((name, runpaths)) = queue.pop_front() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let local = runpaths
+                .iter()
+                .chain(self.local_dirs.iter())
+                .map(|dir| dir.join(&name))
+                .find(|it| it.is_file() && self.runs_with_exe(it))
+                .or_else(|| self.candidate(&self.overlay_libs, &name));
+            let library = match (local, self.candidate(&self.sysroot_libs, &name)) {
+                (Some(library), _)
+                    if self
+                        .sysroot_lib_dirs
+                        .iter()
+                        .any(|it| library.starts_with(it)) =>
+                {
+                    debug!("{} is a system library", name);
+                    continue;
+                }
+                (Some(library), _) => library,
+                // the android sysroot ships the C++ runtime, but devices don't have it
+                (None, Some(library)) if name == "libc++_shared.so" => library,
+                (None, Some(_)) => {
+                    debug!("{} is a system library", name);
+                    continue;
+                }
+                (None, None) => {
+                    debug!("{} not found, assuming the device provides it", name);
+                    continue;
+                }
+            };
+            debug!("{} resolved to {}", name, library.display());
+            if let Some(Needed { names, runpaths }) = needed_of(&library)? {
+                queue.extend(names.into_iter().map(|name| (name, runpaths.clone())));
+            }
+            libraries.push(library);
+        }
+        Ok(libraries)
+    }
+}
+
+/// The `e_machine` of an ELF file, `None` if it is not one.
+fn elf_machine(path: &Path) -> Option<u16> {
+    let mut header = [0u8; 20];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .ok()?;
+    if !header.starts_with(b"\x7fELF") {
+        return None;
+    }
+    let machine = [header[18], header[19]];
+    match header[5] {
+        1 => Some(u16::from_le_bytes(machine)),
+        2 => Some(u16::from_be_bytes(machine)),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+struct Needed {
+    names: Vec<String>,
+    runpaths: Vec<PathBuf>,
+}
+
+/// The `DT_NEEDED` and runpath entries of an ELF object, `None` if it is not an ELF file.
+fn needed_libraries(path: &Path) -> Result<Option<Needed>> {
+    let data = fs::read(path)?;
+    if !data.starts_with(b"\x7fELF") {
+        return Ok(None);
+    }
+    let elf = Elf::parse(&data).with_context(|| format!("Could not parse {}", path.display()))?;
+    let origin = path
+        .canonicalize()?
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    Ok(Some(Needed {
+        names: elf.libraries.iter().map(|it| it.to_string()).collect(),
+        runpaths: runpaths(&elf.runpaths, &elf.rpaths, &origin),
+    }))
+}
+
+/// The directories of the `DT_RUNPATH` entries, or of the `DT_RPATH` ones when there is no
+/// `DT_RUNPATH`, as the dynamic loader ignores them in that case.
+fn runpaths(runpath: &[&str], rpath: &[&str], origin: &Path) -> Vec<PathBuf> {
+    let entries = if runpath.is_empty() { rpath } else { runpath };
+    entries
+        .iter()
+        .flat_map(|it| it.split(':'))
+        .filter(|it| !it.is_empty())
+        .map(|it| expand_origin(it, origin))
+        .collect()
+}
+
+fn expand_origin(runpath: &str, origin: &Path) -> PathBuf {
+    let origin = origin.to_string_lossy();
+    PathBuf::from(
+        runpath
+            .replace("${ORIGIN}", &origin)
+            .replace("$ORIGIN", &origin),
+    )
+}
+
+/// Maps the file names of the shared libraries found in `dirs` to their path.
+fn index_libraries(dirs: &[PathBuf], max_depth: usize) -> HashMap<String, Vec<PathBuf>> {
+    let mut index: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    for dir in dirs {
+        for entry in WalkDir::new(dir)
+            .max_depth(max_depth)
+            .follow_links(true)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .filter_map(|it| it.ok())
+        {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type().is_file() && (name.ends_with(".so") || name.contains(".so.")) {
+                index.entry(name).or_default().push(entry.into_path());
+            }
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_origin() {
+        let origin = Path::new("/work/target/debug");
+        assert_eq!(
+            PathBuf::from("/work/target/debug/../lib"),
+            expand_origin("$ORIGIN/../lib", origin)
+        );
+        assert_eq!(
+            PathBuf::from("/work/target/debug/deps"),
+            expand_origin("${ORIGIN}/deps", origin)
+        );
+        assert_eq!(PathBuf::from("/opt/lib"), expand_origin("/opt/lib", origin));
+    }
+
+    #[test]
+    fn test_runpaths() {
+        let origin = Path::new("/work/lib");
+        assert_eq!(
+            runpaths(&["$ORIGIN:/opt/a", "/opt/b"], &["/opt/rpath"], origin),
+            vec![
+                PathBuf::from("/work/lib"),
+                PathBuf::from("/opt/a"),
+                PathBuf::from("/opt/b")
+            ]
+        );
+        assert_eq!(
+            runpaths(&[], &["/opt/rpath::$ORIGIN/.."], origin),
+            vec![PathBuf::from("/opt/rpath"), PathBuf::from("/work/lib/..")]
+        );
+    }
+
+    fn touch(path: &Path) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "").unwrap();
+        path.to_path_buf()
+    }
+
+    fn needed(names: &[&str], runpaths: &[&Path]) -> Needed {
+        Needed {
+            names: names.iter().map(|it| it.to_string()).collect(),
+            runpaths: runpaths.iter().map(|it| it.to_path_buf()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let exe = root.join("target/debug/deps/app");
+        let runpath = root.join("runpath");
+        let overlay = root.join("overlay");
+        let sysroot = root.join("sysroot");
+
+        // found in the runpath before the deps directory
+        let libfirst = touch(&runpath.join("libfirst.so"));
+        touch(&root.join("target/debug/deps/libfirst.so"));
+        // in the deps directory, needing libdep through its own runpath, which needs it back
+        let liblocal = touch(&root.join("target/debug/deps/liblocal.so"));
+        let libdep = touch(&root.join("vendor/libdep.so"));
+        // in the overlays, needing liblocal as well
+        let liboverlay = touch(&overlay.join("lib/liboverlay.so.1"));
+        // the system libraries are on the device, but not the C++ runtime
+        touch(&sysroot.join("usr/lib/libc.so.6"));
+        touch(&sysroot.join("usr/lib/libm.so.6"));
+        let libcxx = touch(&sysroot.join("usr/lib/aarch64-linux-android/libc++_shared.so"));
+
+        let graph: HashMap<PathBuf, Needed> = vec![
+            (libfirst.clone(), needed(&["libc.so.6"], &[])),
+            (
+                liblocal.clone(),
+                needed(&["libdep.so"], &[&root.join("vendor")]),
+            ),
+            (libdep.clone(), needed(&["libm.so.6", "liblocal.so"], &[])),
+            (liboverlay.clone(), needed(&["liblocal.so"], &[])),
+        ]
+        .into_iter()
+        .collect();
+        let search = LibrarySearch::new(&exe, &[overlay], Some(&sysroot));
+        let libraries = search
+            .resolve(
+                needed(
+                    &[
+                        "libfirst.so",
+                        "liblocal.so",
+                        "liboverlay.so.1",
+                        "libc.so.6",
+                        "libc++_shared.so",
+                        "libmissing.so",
+                    ],
+                    &[&runpath],
+                ),
+                |path| Ok(graph.get(path).cloned()),
+            )
+            .unwrap();
+        assert_eq!(
+            libraries,
+            vec![libfirst, liblocal, liboverlay, libcxx, libdep]
+        );
+    }
+
+    /// An ELF header for `machine`, enough for [`elf_machine`].
+    fn elf_header(path: &Path, machine: u16) -> PathBuf {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut header = b"\x7fELF\x02\x01\x01".to_vec();
+        header.resize(18, 0);
+        header.extend(machine.to_le_bytes());
+        header.resize(64, 0);
+        fs::write(path, header).unwrap();
+        path.to_path_buf()
+    }
+
+    #[test]
+    fn test_resolve_picks_the_cxx_runtime_of_the_exe_abi() {
+        let dir = tempfile::tempdir().unwrap();
+        let sysroot = dir.path().join("sysroot");
+        let abis = [
+            ("aarch64-linux-android", 183),
+            ("arm-linux-androideabi", 40),
+            ("i686-linux-android", 3),
+            ("riscv64-linux-android", 243),
+            ("x86_64-linux-android", 62),
+        ];
+        let runtime = |triple: &str| {
+            sysroot
+                .join("usr/lib")
+                .join(triple)
+                .join("libc++_shared.so")
+        };
+        for (triple, machine) in &abis {
+            elf_header(&runtime(triple), *machine);
+        }
+        for (triple, machine) in &abis {
+            let exe = elf_header(&dir.path().join(triple).join("app"), *machine);
+            let search = LibrarySearch::new(&exe, &[], Some(&sysroot));
+            let libraries = search
+                .resolve(needed(&["libc++_shared.so"], &[]), |_| Ok(None))
+                .unwrap();
+            assert_eq!(libraries, vec![runtime(triple)]);
+        }
+    }
+
+    #[test]
+    fn test_resolve_skips_libraries_found_in_the_sysroot() {
+        let dir = tempfile::tempdir().unwrap();
+        let sysroot = dir.path().join("sysroot");
+        // a runpath pointing into the sysroot does not make its libraries local
+        touch(&sysroot.join("lib/libz.so.1"));
+        let search = LibrarySearch::new(&dir.path().join("app"), &[], Some(&sysroot));
+        let libraries = search
+            .resolve(needed(&["libz.so.1"], &[&sysroot.join("lib")]), |_| {
+                Ok(None)
+            })
+            .unwrap();
+        assert!(libraries.is_empty());
+    }
+}
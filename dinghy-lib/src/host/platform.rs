@@ -89,4 +89,8 @@ impl Platform for HostPlatform {
     fn sysroot(&self) -> Result<Option<std::path::PathBuf>> {
         Ok(Some(std::path::PathBuf::from("/")))
     }
+
+    fn toolchain(&self) -> Option<std::path::PathBuf> {
+        None
+    }
 }
@@ -0,0 +1,97 @@
+use crate::errors::*;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+static HOOKS: Mutex<Vec<(usize, Option<Hook>)>> = Mutex::new(Vec::new());
+static NEXT_HOOK_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps the process alive on Ctrl-C so that devices can be cleaned up.
+///
+/// The process running on the device gets the signal as well (it is in the same process group),
+/// so `run_app` returns and the caller can cleanup before exiting. Programs which are not local
+/// children are killed by the hooks registered with [`on_interrupt`].
+pub fn install_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        log::debug!("Interrupted");
+        interrupt();
+    })
+    .context("Could not install Ctrl-C handler")
+}
+
+/// Flags the process as interrupted and runs the registered hooks.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    let mut hooks = HOOKS.lock().unwrap_or_else(|e| e.into_inner());
+    for (_, hook) in hooks.iter_mut() {
+        if let Some(hook) = hook.take() {
+            hook()
+        }
+    }
+}
+
+/// Whether Ctrl-C was hit since the handler was installed.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Registers a hook run once if the process gets interrupted before the returned guard is
+/// dropped, typically to kill a program running on a device.
+///
+/// Dropping the guard after an interruption waits for the hook to complete, running it if the
+/// handler did not get to it yet.
+pub fn on_interrupt(hook: impl FnOnce() + Send + 'static) -> InterruptHook {
+    let id = NEXT_HOOK_ID.fetch_add(1, Ordering::SeqCst);
+    HOOKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((id, Some(Box::new(hook))));
+    InterruptHook { id }
+}
+
+#[must_use = "the hook is unregistered when the guard is dropped"]
+pub struct InterruptHook {
+    id: usize,
+}
+
+impl Drop for InterruptHook {
+    fn drop(&mut self) {
+        let mut hooks = HOOKS.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(index) = hooks.iter().position(|(id, _)| *id == self.id) else {
+            return;
+        };
+        let (_, hook) = hooks.remove(index);
+        if let Some(hook) = hook.filter(|_| interrupted()) {
+            hook()
+        }
+    }
+}
+
+/// Interrupts the process, as a Ctrl-C would, if the returned guard is still alive after
+/// `timeout`.
+pub fn watchdog(timeout: Duration) -> Watchdog {
+    let (cancel, cancelled) = mpsc::channel::<()>();
+    std::thread::spawn(move || {
+        if let Err(mpsc::RecvTimeoutError::Timeout) = cancelled.recv_timeout(timeout) {
+            log::debug!("Timed out after {:?}", timeout);
+            TIMED_OUT.store(true, Ordering::SeqCst);
+            interrupt();
+        }
+    });
+    Watchdog { _cancel: cancel }
+}
+
+/// Whether a [`watchdog`] expired.
+pub fn timed_out() -> bool {
+    TIMED_OUT.load(Ordering::SeqCst)
+}
+
+#[must_use = "the watchdog is cancelled when the guard is dropped"]
+pub struct Watchdog {
+    _cancel: mpsc::Sender<()>,
+}
@@ -7,12 +7,18 @@ mod android;
 #[cfg(target_os = "macos")]
 mod apple;
 pub mod config;
+pub mod coverage;
 pub mod device;
+pub mod doctor;
+pub mod elf;
 mod host;
+pub mod interrupt;
 pub mod overlay;
 pub mod platform;
 pub mod plugin;
 pub mod project;
+mod qemu;
+pub mod report;
 mod script;
 mod ssh;
 mod toolchain;
@@ -26,10 +32,10 @@ use crate::config::PlatformConfiguration;
 
 use crate::platform::regular_platform::RegularPlatform;
 use crate::project::Project;
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
 use dyn_clone::DynClone;
 use std::fmt::Display;
-use std::{path, sync};
+use std::{path, process, sync, time};
 
 use crate::errors::Result;
 
@@ -44,7 +50,7 @@ impl Dinghy {
         if let Some(man) = host::HostManager::probe(conf) {
             managers.push(Box::new(man));
         }
-        if let Some(man) = android::AndroidManager::probe() {
+        if let Some(man) = android::AndroidManager::probe(conf.clone()) {
             managers.push(Box::new(man));
         }
         if let Some(man) = script::ScriptDeviceManager::probe(conf.clone()) {
@@ -87,7 +93,8 @@ impl Dinghy {
             );
         }
         for (platform_name, platform_conf) in &conf.platforms {
-            if platform_name == "host" {
+            // these ones configure generated platforms
+            if platform_name == "host" || platform_name.starts_with("auto-android-") {
                 continue;
             }
             let rustc_triple = platform_conf
@@ -99,16 +106,25 @@ impl Dinghy {
                 platform_name.to_string(),
                 rustc_triple.clone(),
                 platform_conf
-                    .toolchain
-                    .clone()
-                    .map(|it| path::PathBuf::from(it))
-                    .or(dirs::home_dir()
-                        .map(|it| it.join(".dinghy").join("toolchain").join(platform_name)))
+                    .toolchain_path(platform_name)
                     .with_context(|| format!("Toolchain missing for platform {}", platform_name))?,
             )
             .with_context(|| format!("Could not assemble platform {}", platform_name))?;
             platforms.push((pf.id(), sync::Arc::new(pf)));
         }
+        // qemu devices emulate the platforms, so they can only be probed once all of them are known
+        let all_platforms = platforms
+            .iter()
+            .map(|(_, pf)| pf.clone())
+            .collect::<Vec<_>>();
+        if let Some(man) = qemu::QemuManager::probe(&all_platforms) {
+            devices.extend(
+                man.devices()
+                    .context("Could not list qemu devices")?
+                    .into_iter()
+                    .map(sync::Arc::new),
+            );
+        }
         Ok(Dinghy { devices, platforms })
     }
 
@@ -152,19 +168,91 @@ pub trait Device: std::fmt::Debug + Display + DeviceCompatibility + DynClone {
 
     fn id(&self) -> &str;
 
+    fn kind(&self) -> DeviceKind;
+
     fn name(&self) -> &str;
 
+    /// Runs the executable. A program that ran but failed is not an error: the returned
+    /// outcome tells how it ended.
     fn run_app(
         &self,
         project: &Project,
         build: &Build,
         args: &[&str],
         envs: &[&str],
-    ) -> Result<BuildBundle>;
+    ) -> Result<RunOutcome>;
+
+    /// How long a run may last before it is killed, when the device configuration says so.
+    fn timeout(&self) -> Option<time::Duration> {
+        None
+    }
+
+    /// Command to run executables through, when the device configuration says so.
+    fn launcher(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Copies the files of the bundle directory on the device matching the `collect` globs to
+    /// `destination`, and returns their paths relative to it.
+    fn collect_files(
+        &self,
+        _build_bundle: &BuildBundle,
+        _patterns: &[String],
+        _destination: &path::Path,
+    ) -> Result<Vec<path::PathBuf>> {
+        bail!(
+            "Collecting files is not supported on {} devices",
+            self.kind()
+        )
+    }
+
+    /// Rustc triples the device was found to run, best match first, for devices which probe
+    /// them.
+    fn detected_rustc_triples(&self) -> Result<Option<Vec<String>>> {
+        Ok(None)
+    }
+
+    /// Why the device can not be used, for devices which are listed anyway, like unauthorized
+    /// android devices. They are compatible with no platform.
+    fn unusable_reason(&self) -> Option<String> {
+        None
+    }
+
+    /// The platform to use when only the device is selected, among the ones it is compatible
+    /// with. The first one by default.
+    fn preferred_platform(
+        &self,
+        platforms: &[sync::Arc<Box<dyn Platform>>],
+    ) -> Option<sync::Arc<Box<dyn Platform>>> {
+        platforms.first().cloned()
+    }
 }
 
 dyn_clone::clone_trait_object!(Device);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Android,
+    Apple,
+    Plugin,
+    Qemu,
+    Script,
+    Ssh,
+}
+
+impl Display for DeviceKind {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str(match self {
+            DeviceKind::Android => "android",
+            DeviceKind::Apple => "apple",
+            DeviceKind::Plugin => "plugin",
+            DeviceKind::Qemu => "qemu",
+            DeviceKind::Script => "script",
+            DeviceKind::Ssh => "ssh",
+        })
+    }
+}
+
 pub trait DeviceCompatibility {
     fn is_compatible_with_regular_platform(&self, _platform: &RegularPlatform) -> bool {
         false
@@ -195,6 +283,7 @@ pub trait Platform: std::fmt::Debug {
 
     fn strip(&self, build: &mut Build) -> Result<()>;
     fn sysroot(&self) -> Result<Option<path::PathBuf>>;
+    fn toolchain(&self) -> Option<path::PathBuf>;
 }
 
 impl Display for dyn Platform {
@@ -215,6 +304,10 @@ pub struct Build {
     pub runnable: Runnable,
     pub target_path: path::PathBuf,
     pub files_in_run_args: Vec<path::PathBuf>,
+    /// Command the executable is run through, like `taskset -c 2`, empty to run it directly
+    pub launcher: Vec<String>,
+    /// Sysroot of the platform, where debuggers find the symbols of the system libraries
+    pub sysroot: Option<path::PathBuf>,
 }
 
 #[derive(Clone, Debug)]
@@ -224,7 +317,19 @@ pub struct SetupArgs {
     pub envs: Vec<String>,
     pub cleanup: bool,
     pub strip: bool,
-    pub device_id: Option<String>,
+    pub device_ids: Vec<String>,
+    /// Seconds after which the runner kills the run
+    pub timeout: Option<u64>,
+    /// Start the executable under a debugger instead of running it
+    pub debugger: bool,
+    /// Command to run the executable through, overriding the device and platform configuration
+    pub launcher: Vec<String>,
+    /// Globs of the files to copy back from the device, on top of the configured ones
+    pub collect: Vec<String>,
+    /// Collect the code coverage profiles of the runs
+    pub coverage: bool,
+    /// Write a test report of each run in target/dinghy/reports
+    pub report: bool,
 }
 
 impl SetupArgs {
@@ -246,7 +351,27 @@ impl SetupArgs {
         if self.strip {
             extra_args.push_str("--strip ")
         }
-        if let Some(device_id) = &self.device_id {
+        if let Some(timeout) = self.timeout {
+            extra_args.push_str(&format!("--timeout {} ", timeout))
+        }
+        if self.debugger {
+            extra_args.push_str("--debugger ")
+        }
+        if !self.launcher.is_empty() {
+            extra_args.push_str(&format!("--launcher {} ", self.launcher.join(",")))
+        }
+        if self.coverage {
+            extra_args.push_str("--coverage ")
+        }
+        if self.report {
+            extra_args.push_str("--report ")
+        }
+        for glob in &self.collect {
+            extra_args.push_str("--collect ");
+            extra_args.push_str(glob);
+            extra_args.push(' ');
+        }
+        for device_id in &self.device_ids {
             extra_args.push_str("-d ");
             extra_args.push_str(&device_id);
             extra_args.push(' ');
@@ -303,6 +428,75 @@ impl BuildBundle {
     }
 }
 
+/// How a program run on a device ended.
+#[derive(Clone, Debug)]
+pub struct RunOutcome {
+    pub bundle: BuildBundle,
+    /// Exit code of the program, `None` when it was terminated by a signal
+    pub exit_code: Option<i32>,
+    /// Signal that terminated the program
+    pub signal: Option<i32>,
+    pub duration: time::Duration,
+}
+
+impl RunOutcome {
+    /// Outcome of a program that ran in a local process.
+    pub fn from_status(
+        bundle: BuildBundle,
+        status: process::ExitStatus,
+        started: time::Instant,
+    ) -> RunOutcome {
+        use std::os::unix::process::ExitStatusExt;
+        RunOutcome {
+            bundle,
+            exit_code: status.code(),
+            signal: status.signal(),
+            duration: started.elapsed(),
+        }
+    }
+
+    /// Outcome of a program run by a shell, which reports a program terminated by signal N with
+    /// the 128 + N exit code.
+    pub fn from_shell_exit_code(
+        bundle: BuildBundle,
+        code: i32,
+        started: time::Instant,
+    ) -> RunOutcome {
+        let signal = Some(code - 128).filter(|signal| (1..65).contains(signal));
+        RunOutcome {
+            bundle,
+            exit_code: if signal.is_some() { None } else { Some(code) },
+            signal,
+            duration: started.elapsed(),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    /// The code the runner exits with, following the shell convention for signals.
+    pub fn runner_exit_code(&self) -> i32 {
+        self.exit_code
+            .or(self.signal.map(|signal| 128 + signal))
+            .unwrap_or(1)
+    }
+}
+
+impl Display for RunOutcome {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.exit_code, self.signal) {
+            (_, Some(signal)) => match utils::signal_name(signal) {
+                Some(name) => write!(fmt, "terminated by signal {} ({})", signal, name)?,
+                None => write!(fmt, "terminated by signal {}", signal)?,
+            },
+            (Some(code), None) => write!(fmt, "exited with code {}", code)?,
+            (None, None) => write!(fmt, "exited")?,
+        }
+        write!(fmt, " after {:.2}s", self.duration.as_secs_f64())
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct Runnable {
     pub id: String,
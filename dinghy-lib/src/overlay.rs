@@ -53,6 +53,20 @@ impl Overlayer {
             work_dir: project.overlay_work_dir(platform)?,
         };
 
+        overlayer.apply_overlay(Overlayer::overlays(
+            configuration,
+            &overlayer.platform_id,
+            project,
+        )?)
+    }
+
+    /// The overlays of a platform, from its configuration and from the `.dinghy/overlay/<platform>`
+    /// directories of the project, its parents and the home directory.
+    pub fn overlays(
+        configuration: &PlatformConfiguration,
+        platform_id: &str,
+        project: &Project,
+    ) -> Result<Vec<Overlay>> {
         let mut path_to_try = vec![];
         let project_path = project.project_dir()?;
         let mut current_path = project_path.as_path();
@@ -61,7 +75,7 @@ impl Overlayer {
                 current_path
                     .join(".dinghy")
                     .join("overlay")
-                    .join(&overlayer.platform_id),
+                    .join(platform_id),
             );
             if let Some(parent_path) = current_path.parent() {
                 current_path = parent_path;
@@ -71,25 +85,23 @@ impl Overlayer {
         }
 
         // Project may be outside home directory. So add it too.
-        if let Some(dinghy_home_dir) = home_dir().map(|it| {
-            it.join(".dinghy")
-                .join("overlay")
-                .join(&overlayer.platform_id)
-        }) {
+        if let Some(dinghy_home_dir) =
+            home_dir().map(|it| it.join(".dinghy").join("overlay").join(platform_id))
+        {
             if !path_to_try.contains(&dinghy_home_dir) {
                 path_to_try.push(dinghy_home_dir)
             }
         }
 
-        overlayer.apply_overlay(
-            Overlayer::from_conf(configuration)?
-                .into_iter()
-                .chain(path_to_try.into_iter().flat_map(|path_to_try| {
+        Ok(Overlayer::from_conf(configuration)?
+            .into_iter()
+            .chain(
+                path_to_try.into_iter().flat_map(|path_to_try| {
                     Overlayer::from_directory(path_to_try).unwrap_or_default()
-                }))
-                .unique_by(|overlay| overlay.id.clone())
-                .collect_vec(),
-        )
+                }),
+            )
+            .unique_by(|overlay| overlay.id.clone())
+            .collect_vec())
     }
 
     fn from_conf(configuration: &PlatformConfiguration) -> Result<Vec<Overlay>> {
@@ -37,21 +37,27 @@ impl RegularPlatform {
         rustc_triple: String,
         toolchain_path: P,
     ) -> Result<Box<dyn Platform>> {
+        let toolchain = Self::toolchain_config(&configuration, rustc_triple, toolchain_path)?;
+        Self::new_with_tc(configuration, id, toolchain)
+    }
+
+    /// Discovers the compiler and sysroot of the toolchain a platform is configured with.
+    pub fn toolchain_config<P: AsRef<Path>>(
+        configuration: &PlatformConfiguration,
+        rustc_triple: String,
+        toolchain_path: P,
+    ) -> Result<ToolchainConfig> {
         if let Some(prefix) = configuration.deb_multiarch.clone() {
-            return Ok(Box::new(RegularPlatform {
-                configuration,
-                id,
-                toolchain: ToolchainConfig {
-                    bin_dir: "/usr/bin".into(),
-                    rustc_triple,
-                    root: "/".into(),
-                    sysroot: Some("/".into()),
-                    cc: "gcc".to_string(),
-                    cxx: "c++".to_string(),
-                    binutils_prefix: prefix.clone(),
-                    cc_prefix: prefix.clone(),
-                },
-            }));
+            return Ok(ToolchainConfig {
+                bin_dir: "/usr/bin".into(),
+                rustc_triple,
+                root: "/".into(),
+                sysroot: Some("/".into()),
+                cc: "gcc".to_string(),
+                cxx: "c++".to_string(),
+                binutils_prefix: prefix.clone(),
+                cc_prefix: prefix.clone(),
+            });
         }
         let toolchain_path = toolchain_path.as_ref();
         let toolchain_bin_path = toolchain_path.join("bin");
@@ -79,7 +85,7 @@ impl RegularPlatform {
             .to_string();
         let sysroot = find_sysroot(&toolchain_path)?;
 
-        let toolchain = ToolchainConfig {
+        Ok(ToolchainConfig {
             bin_dir,
             rustc_triple,
             root: toolchain_path.into(),
@@ -88,8 +94,7 @@ impl RegularPlatform {
             cxx: "c++".to_string(),
             binutils_prefix: tc_triple.clone(),
             cc_prefix: tc_triple,
-        };
-        Self::new_with_tc(configuration, id, toolchain)
+        })
     }
 
     pub fn new_with_tc(
@@ -194,6 +199,10 @@ impl Platform for RegularPlatform {
     fn sysroot(&self) -> Result<Option<std::path::PathBuf>> {
         Ok(self.toolchain.sysroot.clone())
     }
+
+    fn toolchain(&self) -> Option<std::path::PathBuf> {
+        Some(self.toolchain.root.clone())
+    }
 }
 
 fn find_sysroot<P: AsRef<Path>>(toolchain_path: P) -> Result<Option<PathBuf>> {
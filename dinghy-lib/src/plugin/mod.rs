@@ -63,7 +63,11 @@ impl PluginManager {
             .filter_map(|(id, conf)| {
                 if self.conf.script_devices.get(&id).is_none() {
                     debug!("registering script device {id} from {provider}");
-                    Some(Box::new(crate::script::ScriptDevice { id, conf }) as _)
+                    Some(Box::new(crate::script::ScriptDevice {
+                        id,
+                        conf,
+                        plugin: Some(provider.to_string()),
+                    }) as _)
                 } else {
                     debug!("ignoring script device {id} from {provider} as is was already registered in configuration");
                     None
@@ -80,10 +84,7 @@ impl PluginManager {
         ssh_devices.into_iter().filter_map(|(id, conf)| {
             if self.conf.script_devices.get(&id).is_none() {
                 debug!("registering ssh device {id} from {provider}");
-                Some(Box::new(crate::ssh::SshDevice {
-                    id,
-                    conf,
-                }) as _)
+                Some(Box::new(crate::ssh::SshDevice::new(id, conf, Some(provider.to_string()))) as _)
             } else {
                 debug!("ignoring ssh device {id} from {provider} as is was already registered in configuration");
                 None
@@ -203,7 +204,7 @@ fn get_platforms_from_plugin(plugin: &str) -> Result<BTreeMap<String, Box<dyn Pl
 
 // dinghy will auto-detect any executable in the PATH that starts with `cargo-dinghy-` and try to
 // use it as a plugin.
-fn auto_detect_plugins() -> Vec<String> {
+pub(crate) fn auto_detect_plugins() -> Vec<String> {
     let mut binaries = Vec::new();
 
     if let Some(paths) = env::var_os("PATH") {
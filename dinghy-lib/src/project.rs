@@ -5,22 +5,85 @@ use crate::Result;
 use crate::Runnable;
 use anyhow::anyhow;
 use anyhow::Context;
-use cargo_metadata::Metadata;
+use cargo_metadata::{Metadata, Package};
 use fs_err as fs;
 use ignore::WalkBuilder;
+use itertools::Itertools;
 use log::{debug, trace};
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+/// Cargo subcommands accepting the `-p`, `--workspace` and `--exclude` package selection options.
+static PACKAGE_SELECTING_SUBCOMMANDS: &[&str] = &[
+    "b", "bench", "build", "c", "check", "clippy", "d", "doc", "t", "test",
+];
+
 #[derive(Debug)]
 pub struct Project {
     pub conf: Arc<Configuration>,
     pub metadata: Metadata,
 }
 
+/// Triple filtering from the `[package.metadata.dinghy]` section of a package manifest.
+#[derive(Clone, Debug, Default)]
+pub struct PackageFilter {
+    pub allowed_rustc_triples: Option<Vec<String>>,
+    pub ignored_rustc_triples: Vec<String>,
+}
+
+impl PackageFilter {
+    pub fn from_package(package: &Package) -> PackageFilter {
+        let triples = |key: &str| {
+            package.metadata["dinghy"][key].as_array().map(|triples| {
+                triples
+                    .iter()
+                    .filter_map(|it| it.as_str())
+                    .map(|it| it.to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        PackageFilter {
+            allowed_rustc_triples: triples("allowed_rustc_triples"),
+            ignored_rustc_triples: triples("ignored_rustc_triples").unwrap_or_default(),
+        }
+    }
+
+    pub fn allows(&self, rustc_triple: &str) -> bool {
+        self.allowed_rustc_triples
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|it| it == rustc_triple))
+            && !self
+                .ignored_rustc_triples
+                .iter()
+                .any(|it| it == rustc_triple)
+    }
+}
+
+/// Error raised when the packages explicitly selected for a cargo command are all excluded by
+/// their dinghy metadata on the target platform.
+#[derive(Debug)]
+pub struct PackagesFilteredOut {
+    pub packages: Vec<String>,
+    pub platform_id: String,
+}
+
+impl fmt::Display for PackagesFilteredOut {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "Packages {} are filtered out on platform {}",
+            self.packages.join(", "),
+            self.platform_id
+        )
+    }
+}
+
+impl std::error::Error for PackagesFilteredOut {}
+
 impl Project {
     pub fn new(conf: &Arc<Configuration>, metadata: Metadata) -> Project {
         Project {
@@ -29,6 +92,85 @@ impl Project {
         }
     }
 
+    /// Workspace members along with whether their dinghy metadata allows building them for
+    /// `rustc_triple`.
+    pub fn workspace_members_for(&self, rustc_triple: &str) -> Vec<(String, bool)> {
+        self.metadata
+            .packages
+            .iter()
+            .filter(|package| self.metadata.workspace_members.contains(&package.id))
+            .map(|package| {
+                (
+                    package.name.clone(),
+                    PackageFilter::from_package(package).allows(rustc_triple),
+                )
+            })
+            .collect()
+    }
+
+    /// Rewrite the package selection of a cargo command so that it skips the workspace
+    /// members filtered out on the platform, failing with `PackagesFilteredOut` if a package
+    /// that was explicitly asked for is one of them.
+    pub fn filter_cargo_args(
+        &self,
+        platform: &dyn Platform,
+        args: &[String],
+    ) -> Result<Vec<String>> {
+        let members = self.workspace_members_for(platform.rustc_triple());
+        let root_package = self.metadata.root_package().map(|it| it.name.as_str());
+        let default_members = self.workspace_default_members()?;
+        filter_package_args(args, &members, root_package, default_members.as_deref()).map_err(
+            |packages| {
+                PackagesFilteredOut {
+                    packages,
+                    platform_id: platform.id(),
+                }
+                .into()
+            },
+        )
+    }
+
+    /// Names of the packages of the `default-members` of the workspace, if it declares some.
+    ///
+    /// The version of cargo_metadata in use does not expose them, so they are read from the
+    /// workspace manifest.
+    fn workspace_default_members(&self) -> Result<Option<Vec<String>>> {
+        let root = self.metadata.workspace_root.as_std_path();
+        let manifest_path = root.join("Cargo.toml");
+        let manifest: toml::Value = toml::from_str(&fs::read_to_string(&manifest_path)?)
+            .with_context(|| format!("Parsing {}", manifest_path.display()))?;
+        let Some(default_members) = manifest
+            .get("workspace")
+            .and_then(|it| it.get("default-members"))
+            .and_then(|it| it.as_array())
+        else {
+            return Ok(None);
+        };
+        let mut globs = globset::GlobSetBuilder::new();
+        for member in default_members.iter().filter_map(|it| it.as_str()) {
+            let member = member.trim_start_matches("./").trim_end_matches('/');
+            // the workspace root itself has an empty relative path
+            let member = if member == "." { "" } else { member };
+            globs.add(globset::Glob::new(member)?);
+        }
+        let globs = globs.build()?;
+        Ok(Some(
+            self.metadata
+                .packages
+                .iter()
+                .filter(|package| self.metadata.workspace_members.contains(&package.id))
+                .filter(|package| {
+                    package
+                        .manifest_path
+                        .parent()
+                        .and_then(|dir| dir.as_std_path().strip_prefix(root).ok())
+                        .is_some_and(|dir| globs.is_match(dir))
+                })
+                .map(|package| package.name.clone())
+                .collect(),
+        ))
+    }
+
     pub fn project_dir(&self) -> Result<PathBuf> {
         Ok(self.metadata.workspace_root.clone().into_std_path_buf())
     }
@@ -111,6 +253,108 @@ impl Project {
     }
 }
 
+fn filter_package_args(
+    args: &[String],
+    members: &[(String, bool)],
+    root_package: Option<&str>,
+    default_members: Option<&[String]>,
+) -> std::result::Result<Vec<String>, Vec<String>> {
+    let filtered_out = members
+        .iter()
+        .filter(|(_, allowed)| !allowed)
+        .map(|(name, _)| name.as_str())
+        .collect_vec();
+    if filtered_out.is_empty()
+        || !args
+            .first()
+            .is_some_and(|it| PACKAGE_SELECTING_SUBCOMMANDS.contains(&it.as_str()))
+    {
+        return Ok(args.to_vec());
+    }
+
+    let cargo_args = args
+        .iter()
+        .take_while(|it| *it != "--")
+        .map(|it| it.as_str())
+        .collect_vec();
+    let option_values = |short: Option<&str>, long: &str| {
+        let long_eq = format!("{}=", long);
+        cargo_args
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, arg)| {
+                if Some(*arg) == short || *arg == long {
+                    cargo_args.get(ix + 1).copied()
+                } else if let Some(value) = arg.strip_prefix(long_eq.as_str()) {
+                    Some(value)
+                } else {
+                    short
+                        .and_then(|short| arg.strip_prefix(short))
+                        .filter(|value| !value.is_empty() && !arg.starts_with("--"))
+                }
+            })
+            // package specs may carry a version (`foo@1.0.0`), only keep the name
+            .map(|spec| spec.split('@').next().unwrap_or(spec))
+            .collect_vec()
+    };
+    let packages = option_values(Some("-p"), "--package");
+    let excluded = option_values(None, "--exclude");
+    let workspace = cargo_args
+        .iter()
+        .any(|it| *it == "--workspace" || *it == "--all");
+
+    let mut selection = vec![];
+    if !packages.is_empty() {
+        let selected_filtered_out = packages
+            .iter()
+            .filter(|it| filtered_out.contains(it))
+            .map(|it| it.to_string())
+            .collect_vec();
+        if !selected_filtered_out.is_empty() {
+            return Err(selected_filtered_out);
+        }
+    } else if workspace {
+        for package in filtered_out.iter().filter(|it| !excluded.contains(it)) {
+            selection.push("--exclude".to_string());
+            selection.push(package.to_string());
+        }
+    } else if let Some(root_package) = root_package {
+        if filtered_out.contains(&root_package) {
+            return Err(vec![root_package.to_string()]);
+        }
+    } else {
+        // virtual manifest: cargo would build the default members, or else all the members,
+        // select the allowed ones only
+        let candidates = members
+            .iter()
+            .filter(|(name, _)| default_members.is_none_or(|it| it.contains(name)))
+            .collect_vec();
+        if candidates.iter().any(|(_, allowed)| !allowed) {
+            let allowed = candidates
+                .iter()
+                .filter(|(name, allowed)| *allowed && !excluded.contains(&name.as_str()))
+                .collect_vec();
+            if allowed.is_empty() {
+                return Err(candidates.iter().map(|(name, _)| name.clone()).collect());
+            }
+            for (package, _) in allowed {
+                selection.push("-p".to_string());
+                selection.push(package.to_string());
+            }
+        }
+    }
+    if !selection.is_empty() {
+        debug!("Filtering workspace packages with {:?}", selection);
+    }
+
+    Ok(args[..1]
+        .iter()
+        .cloned()
+        .chain(selection)
+        .chain(args[1..].iter().cloned())
+        .collect())
+}
+
 pub fn rec_copy<P1: AsRef<Path>, P2: AsRef<Path>>(
     src: P1,
     dst: P2,
@@ -203,3 +447,75 @@ pub fn rec_copy_excl<P1: AsRef<Path>, P2: AsRef<Path>, P3: AsRef<Path> + ::std::
     );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::filter_package_args;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(|it| it.to_string()).collect()
+    }
+
+    fn members() -> Vec<(String, bool)> {
+        vec![
+            ("app".to_string(), true),
+            ("android-only".to_string(), false),
+            ("lib".to_string(), true),
+        ]
+    }
+
+    #[test]
+    fn test_filter_package_args() {
+        assert_eq!(
+            Ok(args("test -p app -p lib --release -- pass")),
+            filter_package_args(&args("test --release -- pass"), &members(), None, None)
+        );
+        assert_eq!(
+            Ok(args("test --exclude android-only --workspace")),
+            filter_package_args(&args("test --workspace"), &members(), None, None)
+        );
+        assert_eq!(
+            Ok(args("build -p lib")),
+            filter_package_args(&args("build -p lib"), &members(), None, None)
+        );
+        assert_eq!(
+            Ok(args("test")),
+            filter_package_args(&args("test"), &members(), Some("app"), None)
+        );
+        assert_eq!(
+            Ok(args("run")),
+            filter_package_args(&args("run"), &members(), None, None)
+        );
+        assert_eq!(
+            Err(vec!["android-only".to_string()]),
+            filter_package_args(&args("test --package=android-only"), &members(), None, None)
+        );
+        assert_eq!(
+            Err(vec!["android-only".to_string()]),
+            filter_package_args(&args("bench"), &members(), Some("android-only"), None)
+        );
+    }
+
+    #[test]
+    fn test_filter_package_args_with_default_members() {
+        let default_members = vec!["app".to_string(), "android-only".to_string()];
+        assert_eq!(
+            Ok(args("test -p app")),
+            filter_package_args(&args("test"), &members(), None, Some(&default_members))
+        );
+        // nothing to filter out among the default members, cargo picks them itself
+        assert_eq!(
+            Ok(args("test")),
+            filter_package_args(&args("test"), &members(), None, Some(&["lib".to_string()]))
+        );
+        assert_eq!(
+            Err(vec!["android-only".to_string()]),
+            filter_package_args(
+                &args("test"),
+                &members(),
+                None,
+                Some(&["android-only".to_string()])
+            )
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use crate::device::{files_to_collect, make_remote_app};
+use crate::errors::*;
+use crate::interrupt;
+use crate::platform::regular_platform::RegularPlatform;
+use crate::project::Project;
+use crate::utils::{get_current_verbosity, terminate_process, user_facing_log, LogCommandExt};
+use crate::Build;
+use crate::BuildBundle;
+use crate::Device;
+use crate::DeviceCompatibility;
+use crate::DeviceKind;
+use crate::RunOutcome;
+use fs_err as fs;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Instant;
+use walkdir::WalkDir;
+
+#[derive(Clone)]
+pub struct QemuDevice {
+    pub id: String,
+    pub platform_id: String,
+    pub qemu: PathBuf,
+    pub sysroot: Option<PathBuf>,
+}
+
+impl QemuDevice {
+    fn qemu_command(
+        &self,
+        build: &Build,
+        build_bundle: &BuildBundle,
+        envs: &[&str],
+    ) -> Result<Command> {
+        // the launcher runs on the host, wrapping the emulator
+        let mut command = match build.launcher.split_first() {
+            Some((launcher, launcher_args)) => {
+                let mut command = Command::new(launcher);
+                command.args(launcher_args).arg(&self.qemu);
+                command
+            }
+            None => Command::new(&self.qemu),
+        };
+        if let Some(sysroot) = &self.sysroot {
+            command.arg("-L").arg(sysroot);
+        }
+        // -E only sets the variables in the emulated process, not in qemu itself
+        command.arg("-E").arg("RUST_BACKTRACE=1");
+        command.arg("-E").arg("DINGHY=1");
+        command.arg("-E").arg(format!(
+            "LD_LIBRARY_PATH={}",
+            build_bundle.lib_dir.display()
+        ));
+        for env in envs {
+            if !env.contains('=') {
+                bail!("Wrong env spec {}", env)
+            }
+            command.arg("-E").arg(env);
+        }
+        command
+            .arg(&build_bundle.bundle_exe)
+            .current_dir(&build_bundle.bundle_dir);
+        Ok(command)
+    }
+}
+
+impl DeviceCompatibility for QemuDevice {
+    fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
+        platform.id == self.platform_id
+    }
+}
+
+impl Device for QemuDevice {
+    fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        fs::remove_dir_all(&build_bundle.bundle_dir)?;
+        fs::remove_dir_all(&build_bundle.lib_dir)?;
+        Ok(())
+    }
+
+    fn debug_app(
+        &self,
+        _project: &Project,
+        _build: &Build,
+        _args: &[&str],
+        _envs: &[&str],
+    ) -> Result<BuildBundle> {
+        bail!("Debugging is not supported on qemu devices")
+    }
+
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn kind(&self) -> DeviceKind {
+        DeviceKind::Qemu
+    }
+
+    fn name(&self) -> &str {
+        &self.id
+    }
+
+    fn collect_files(
+        &self,
+        build_bundle: &BuildBundle,
+        patterns: &[String],
+        destination: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        // the bundle is on the host, so the files are listed and copied directly
+        let mut listing = String::new();
+        for entry in WalkDir::new(&build_bundle.bundle_dir) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let path = entry.path().strip_prefix(&build_bundle.bundle_dir)?;
+                listing.push_str(&format!("{}\n", path.display()));
+            }
+        }
+        let files = files_to_collect(&listing, patterns)?;
+        for file in &files {
+            let collected = destination.join(file);
+            fs::create_dir_all(collected.parent().unwrap_or(destination))?;
+            fs::copy(build_bundle.bundle_dir.join(file), collected)?;
+        }
+        Ok(files)
+    }
+
+    fn run_app(
+        &self,
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[&str],
+    ) -> Result<RunOutcome> {
+        let build_bundle = make_remote_app(project, build)?;
+        log::info!("Run {} on {}", build.runnable.id, self.id);
+        if get_current_verbosity() < 1 {
+            // we log the full command for verbosity > 1, just log a short message when the user
+            // didn't ask for verbose output
+            user_facing_log(
+                "Running",
+                &format!("{} on {}", build.runnable.id, self.id),
+                0,
+            );
+        }
+        let started = Instant::now();
+        let mut child = self
+            .qemu_command(build, &build_bundle, envs)?
+            .args(args)
+            .log_invocation(1)
+            .spawn()
+            .with_context(|| format!("Couldn't run {}", self.qemu.display()))?;
+        let pid = child.id();
+        let _terminate_on_interrupt = interrupt::on_interrupt(move || terminate_process(pid));
+        let status = child.wait()?;
+        // qemu terminates itself with the signal that terminated the emulated program
+        Ok(RunOutcome::from_status(build_bundle, status, started))
+    }
+}
+
+impl Debug for QemuDevice {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "Qemu {{ \"id\": \"{}\", \"platform\": \"{}\", \"qemu\": {:?} }}",
+            self.id, self.platform_id, self.qemu
+        )
+    }
+}
+
+impl Display for QemuDevice {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{} ({})", self.id, self.qemu.display())
+    }
+}
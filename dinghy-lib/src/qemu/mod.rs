@@ -0,0 +1,128 @@
+use crate::{Device, Platform, PlatformManager, Result};
+use log::debug;
+use std::{path, sync};
+
+mod device;
+
+pub use self::device::QemuDevice;
+
+/// Registers a local qemu user-mode emulation device for every linux platform that has a
+/// matching `qemu-<arch>` binary in the PATH.
+pub struct QemuManager {
+    devices: Vec<QemuDevice>,
+}
+
+impl QemuManager {
+    pub fn probe(platforms: &[sync::Arc<Box<dyn Platform>>]) -> Option<QemuManager> {
+        let mut devices = vec![];
+        for platform in platforms {
+            if platform.is_host() {
+                continue;
+            }
+            let Some(arch) = qemu_arch(platform.rustc_triple()) else {
+                continue;
+            };
+            let qemu = match ::which::which(format!("qemu-{}", arch)) {
+                Ok(qemu) => qemu,
+                Err(_) => {
+                    debug!(
+                        "qemu-{} not found in path, no qemu device for platform {}",
+                        arch,
+                        platform.id()
+                    );
+                    continue;
+                }
+            };
+            let sysroot = match platform.sysroot() {
+                Ok(sysroot) => sysroot.filter(|it| it != path::Path::new("/")),
+                Err(e) => {
+                    debug!(
+                        "Could not get sysroot of platform {}: {:?}",
+                        platform.id(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            debug!("Discovered qemu {:?} for platform {}", qemu, platform.id());
+            devices.push(QemuDevice {
+                id: format!("qemu-{}", platform.id()),
+                platform_id: platform.id(),
+                qemu,
+                sysroot,
+            });
+        }
+        if devices.is_empty() {
+            None
+        } else {
+            Some(QemuManager { devices })
+        }
+    }
+}
+
+impl PlatformManager for QemuManager {
+    fn devices(&self) -> Result<Vec<Box<dyn Device>>> {
+        Ok(self
+            .devices
+            .iter()
+            .map(|it| Box::new(it.clone()) as _)
+            .collect())
+    }
+
+    fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
+        Ok(vec![])
+    }
+}
+
+/// Name of the qemu user-mode architecture able to run binaries for a rustc triple, if any.
+fn qemu_arch(rustc_triple: &str) -> Option<&'static str> {
+    if !rustc_triple.contains("-linux-") || rustc_triple.contains("android") {
+        return None;
+    }
+    let cpu = rustc_triple.split('-').next()?;
+    Some(match cpu {
+        "aarch64" => "aarch64",
+        "aarch64_be" => "aarch64_be",
+        "i586" | "i686" => "i386",
+        "x86_64" => "x86_64",
+        "loongarch64" => "loongarch64",
+        "mips" => "mips",
+        "mipsel" => "mipsel",
+        "mips64" => "mips64",
+        "mips64el" => "mips64el",
+        "powerpc" => "ppc",
+        "powerpc64" => "ppc64",
+        "powerpc64le" => "ppc64le",
+        "riscv64gc" => "riscv64",
+        "riscv32gc" => "riscv32",
+        "s390x" => "s390x",
+        "sparc64" => "sparc64",
+        cpu if cpu.starts_with("arm") || cpu.starts_with("thumb") => {
+            if cpu.ends_with("eb") {
+                "armeb"
+            } else {
+                "arm"
+            }
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::qemu_arch;
+
+    #[test]
+    fn test_qemu_arch() {
+        assert_eq!(Some("aarch64"), qemu_arch("aarch64-unknown-linux-gnu"));
+        assert_eq!(Some("arm"), qemu_arch("armv7-unknown-linux-gnueabihf"));
+        assert_eq!(
+            Some("arm"),
+            qemu_arch("thumbv7neon-unknown-linux-gnueabihf")
+        );
+        assert_eq!(Some("i386"), qemu_arch("i686-unknown-linux-musl"));
+        assert_eq!(Some("riscv64"), qemu_arch("riscv64gc-unknown-linux-gnu"));
+        assert_eq!(None, qemu_arch("aarch64-linux-android"));
+        assert_eq!(None, qemu_arch("aarch64-apple-ios"));
+    }
+}
@@ -0,0 +1,388 @@
+//! Test reports of the runs, for `--report` and `cargo dinghy report`.
+//!
+//! libtest only prints JSON on nightly, so the reports come from its human output: the
+//! `test <name> ... ok` lines and the captured output of the failed tests.
+
+use crate::errors::*;
+use fs_err as fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestStatus {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+impl TestStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TestStatus::Passed => "passed",
+            TestStatus::Failed => "failed",
+            TestStatus::Ignored => "ignored",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+    pub status: TestStatus,
+    /// What a failed test printed, as libtest shows it after the run
+    pub output: Option<String>,
+}
+
+/// Outcome of one runnable on one device.
+#[derive(Clone, Debug)]
+pub struct Report {
+    pub runnable: String,
+    pub package_name: String,
+    pub platform: String,
+    pub device: String,
+    /// Seconds the run took, installation included
+    pub duration: f64,
+    pub exit_code: Option<i32>,
+    pub tests: Vec<TestCase>,
+}
+
+impl Report {
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            runnable: self.runnable.clone(),
+            package_name: self.package_name.clone(),
+            platform: self.platform.clone(),
+            device: self.device.clone(),
+            duration: self.duration,
+            exit_code: self.exit_code,
+            tests: self.tests.iter().map(|test| json::object! {
+                name: test.name.clone(),
+                status: test.status.as_str(),
+                output: test.output.clone(),
+            }).collect::<Vec<_>>(),
+        }
+    }
+
+    pub fn from_json(value: &json::JsonValue) -> Result<Report> {
+        let string = |key: &str| -> Result<String> {
+            value[key]
+                .as_str()
+                .map(|it| it.to_string())
+                .with_context(|| format!("Missing {} in test report", key))
+        };
+        let tests = value["tests"]
+            .members()
+            .map(|test| {
+                let status = match test["status"].as_str() {
+                    Some("passed") => TestStatus::Passed,
+                    Some("failed") => TestStatus::Failed,
+                    Some("ignored") => TestStatus::Ignored,
+                    other => bail!("Unknown test status {:?} in test report", other),
+                };
+                Ok(TestCase {
+                    name: test["name"].as_str().unwrap_or_default().to_string(),
+                    status,
+                    output: test["output"].as_str().map(|it| it.to_string()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Report {
+            runnable: string("runnable")?,
+            package_name: string("package_name")?,
+            platform: string("platform")?,
+            device: string("device")?,
+            duration: value["duration"].as_f64().unwrap_or_default(),
+            exit_code: value["exit_code"].as_i32(),
+            tests,
+        })
+    }
+
+    /// Whether the run failed, even if no test did (a crash, a timeout, a failed install...).
+    pub fn failed(&self) -> bool {
+        self.exit_code != Some(0)
+            || self
+                .tests
+                .iter()
+                .any(|test| test.status == TestStatus::Failed)
+    }
+
+    pub fn count(&self, status: TestStatus) -> usize {
+        self.tests.iter().filter(|it| it.status == status).count()
+    }
+
+    /// Writes the report to [`report_path`], replacing the one of a previous run.
+    pub fn write(&self, target_path: &Path) -> Result<PathBuf> {
+        let path = report_path(target_path, &self.runnable, &self.device);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, self.to_json().pretty(2))?;
+        Ok(path)
+    }
+}
+
+pub fn reports_dir(target_path: &Path) -> PathBuf {
+    target_path.join("dinghy").join("reports")
+}
+
+/// Removes the reports of the previous runs, whose runnables may not even exist anymore.
+pub fn clear_reports(target_path: &Path) -> Result<()> {
+    let dir = reports_dir(target_path);
+    if dir.exists() {
+        fs::remove_dir_all(&dir)?;
+    }
+    Ok(())
+}
+
+/// Where the report of a runnable on a device lives.
+pub fn report_path(target_path: &Path, runnable_id: &str, device_id: &str) -> PathBuf {
+    reports_dir(target_path)
+        .join(runnable_id)
+        .join(format!("{}.json", device_id))
+}
+
+/// Every report found in the target directory, sorted by runnable then device.
+pub fn load_reports(target_path: &Path) -> Result<Vec<Report>> {
+    let mut reports = vec![];
+    let dir = reports_dir(target_path);
+    if !dir.is_dir() {
+        return Ok(reports);
+    }
+    for entry in WalkDir::new(&dir).sort_by(|a, b| a.file_name().cmp(b.file_name())) {
+        let entry = entry?;
+        if !entry.file_type().is_file() || entry.path().extension().is_none_or(|it| it != "json") {
+            continue;
+        }
+        let content = fs::read_to_string(entry.path())?;
+        let report = json::parse(&content)
+            .map_err(|e| anyhow!("{}", e))
+            .and_then(|it| Report::from_json(&it))
+            .with_context(|| format!("Could not read test report {}", entry.path().display()))?;
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Gathers the tests of a run from the lines libtest prints.
+#[derive(Debug, Default)]
+pub struct LibtestParser {
+    tests: Vec<TestCase>,
+    /// The failed test whose output is being read, and the output so far
+    output: Option<(String, Vec<String>)>,
+}
+
+impl LibtestParser {
+    pub fn new() -> LibtestParser {
+        LibtestParser::default()
+    }
+
+    pub fn line(&mut self, line: &str) {
+        let line = line.trim_end();
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|it| it.strip_suffix(" stdout ----"))
+        {
+            self.end_output();
+            self.output = Some((name.to_string(), vec![]));
+            return;
+        }
+        if self.output.is_some() {
+            if line == "failures:" || line.starts_with("test result:") {
+                self.end_output();
+            } else if let Some((_, output)) = self.output.as_mut() {
+                output.push(line.to_string());
+            }
+            return;
+        }
+        let Some((name, result)) = line
+            .strip_prefix("test ")
+            .and_then(|it| it.split_once(" ... "))
+        else {
+            return;
+        };
+        let status = if result == "ok" || result.starts_with("bench:") {
+            TestStatus::Passed
+        } else if result == "FAILED" {
+            TestStatus::Failed
+        } else if result == "ignored" || result.starts_with("ignored, ") {
+            TestStatus::Ignored
+        } else {
+            return;
+        };
+        self.tests.push(TestCase {
+            name: name.to_string(),
+            status,
+            output: None,
+        });
+    }
+
+    pub fn finish(mut self) -> Vec<TestCase> {
+        self.end_output();
+        self.tests
+    }
+
+    fn end_output(&mut self) {
+        let Some((name, mut output)) = self.output.take() else {
+            return;
+        };
+        while output.last().is_some_and(|it| it.is_empty()) {
+            output.pop();
+        }
+        let blank_lines = output.iter().take_while(|it| it.is_empty()).count();
+        output.drain(..blank_lines);
+        if let Some(test) = self.tests.iter_mut().rev().find(|it| it.name == name) {
+            test.output = Some(output.join("\n"));
+        }
+    }
+}
+
+/// Renders reports as JUnit XML, with one test suite per runnable and device.
+///
+/// A run that failed without any failed test (a crash, a timeout...) gets an extra `run` test
+/// case with an error, so that it shows up as well.
+pub fn junit(reports: &[Report]) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for report in reports {
+        let run_error = report.failed() && report.count(TestStatus::Failed) == 0;
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" hostname=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(&format!("{} on {}", report.runnable, report.device)),
+            xml_escape(&report.device),
+            report.tests.len() + run_error as usize,
+            report.count(TestStatus::Failed),
+            run_error as usize,
+            report.count(TestStatus::Ignored),
+            report.duration,
+        ));
+        for test in &report.tests {
+            xml.push_str(&format!(
+                "    <testcase name=\"{}\" classname=\"{}\"",
+                xml_escape(&test.name),
+                xml_escape(&report.package_name),
+            ));
+            match test.status {
+                TestStatus::Passed => xml.push_str("/>\n"),
+                TestStatus::Ignored => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+                TestStatus::Failed => xml.push_str(&format!(
+                    ">\n      <failure message=\"failed\">{}</failure>\n    </testcase>\n",
+                    xml_escape(test.output.as_deref().unwrap_or_default())
+                )),
+            }
+        }
+        if run_error {
+            let message = match report.exit_code {
+                Some(code) => format!("exited with code {}", code),
+                None => "killed".to_string(),
+            };
+            xml.push_str(&format!(
+                "    <testcase name=\"run\" classname=\"{}\">\n      <error message=\"{}\"/>\n    </testcase>\n",
+                xml_escape(&report.package_name),
+                xml_escape(&message),
+            ));
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // not allowed in XML 1.0, like the color codes some tests print
+            c if c.is_control() && c != '\n' && c != '\t' && c != '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(runnable: &str, device: &str) -> Report {
+        Report {
+            runnable: runnable.to_string(),
+            package_name: "app".to_string(),
+            platform: "host".to_string(),
+            device: device.to_string(),
+            duration: 0.5,
+            exit_code: Some(0),
+            tests: vec![TestCase {
+                name: "tests::passes".to_string(),
+                status: TestStatus::Passed,
+                output: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_load_reports_of_the_current_run() {
+        let target = tempfile::tempdir().unwrap();
+        report("app-0123", "pi").write(target.path()).unwrap();
+        report("removed-4567", "pi").write(target.path()).unwrap();
+
+        clear_reports(target.path()).unwrap();
+        report("app-89ab", "pi").write(target.path()).unwrap();
+        report("app-89ab", "host").write(target.path()).unwrap();
+
+        let reports = load_reports(target.path()).unwrap();
+        assert_eq!(
+            reports
+                .iter()
+                .map(|it| (it.runnable.as_str(), it.device.as_str()))
+                .collect::<Vec<_>>(),
+            vec![("app-89ab", "host"), ("app-89ab", "pi")]
+        );
+        assert_eq!(reports[0].tests, report("app-89ab", "host").tests);
+    }
+
+    #[test]
+    fn test_libtest_parser() {
+        let output = "
+running 4 tests
+test tests::fails ... FAILED
+test tests::ignored ... ignored, needs a gpu
+test tests::passes ... ok
+test tests::bench ... bench:          12 ns/iter (+/- 1)
+
+failures:
+
+---- tests::fails stdout ----
+
+thread 'tests::fails' panicked at src/lib.rs:12:9:
+boom
+
+failures:
+    tests::fails
+
+test result: FAILED. 2 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out
+";
+        let mut parser = LibtestParser::new();
+        for line in output.lines() {
+            parser.line(line);
+        }
+        let tests = parser.finish();
+        assert_eq!(
+            tests
+                .iter()
+                .map(|it| (it.name.as_str(), it.status))
+                .collect::<Vec<_>>(),
+            vec![
+                ("tests::fails", TestStatus::Failed),
+                ("tests::ignored", TestStatus::Ignored),
+                ("tests::passes", TestStatus::Passed),
+                ("tests::bench", TestStatus::Passed),
+            ]
+        );
+        assert_eq!(
+            tests[0].output.as_deref(),
+            Some("thread 'tests::fails' panicked at src/lib.rs:12:9:\nboom")
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::config::ScriptDeviceConfiguration;
-use crate::utils::LogCommandExt;
+use crate::device::shell_launcher;
+use crate::utils::{terminate_process, LogCommandExt};
 use crate::*;
 use anyhow::bail;
 use std::{fmt, fs, process};
@@ -8,10 +9,12 @@ use std::{fmt, fs, process};
 pub struct ScriptDevice {
     pub id: String,
     pub conf: ScriptDeviceConfiguration,
+    /// Name of the plugin that provided this device, if any
+    pub plugin: Option<String>,
 }
 
 impl ScriptDevice {
-    fn command(&self, _build: &Build) -> Result<process::Command> {
+    fn command(&self, build: &Build) -> Result<process::Command> {
         if fs::metadata(&self.conf.path).is_err() {
             bail!("Can not read {:?} for {}.", self.conf.path, self.id);
         }
@@ -21,6 +24,8 @@ impl ScriptDevice {
         if let Some(ref pf) = self.conf.platform {
             cmd.env("DINGHY_PLATFORM", &*pf);
         }
+        // the script knows how to run a program on the device, so it applies the launcher too
+        cmd.env("DINGHY_LAUNCHER", shell_launcher(build));
         Ok(cmd)
     }
 }
@@ -37,13 +42,21 @@ impl Device for ScriptDevice {
         _args: &[&str],
         _envs: &[&str],
     ) -> Result<BuildBundle> {
-        unimplemented!()
+        bail!("Debugging is not supported on script devices")
     }
 
     fn id(&self) -> &str {
         &self.id
     }
 
+    fn kind(&self) -> DeviceKind {
+        if self.plugin.is_some() {
+            DeviceKind::Plugin
+        } else {
+            DeviceKind::Script
+        }
+    }
+
     fn name(&self) -> &str {
         &self.id
     }
@@ -54,14 +67,15 @@ impl Device for ScriptDevice {
         build: &Build,
         args: &[&str],
         envs: &[&str],
-    ) -> Result<BuildBundle> {
+    ) -> Result<RunOutcome> {
         let root_dir = build.target_path.join("dinghy");
         let bundle_path = &build.runnable.source;
 
         log::trace!("About to start runner script...");
         let test_data_path = project.link_test_data(&build.runnable)?;
 
-        let status = self
+        let started = std::time::Instant::now();
+        let mut child = self
             .command(build)?
             .arg(&build.runnable.exe)
             .current_dir(&build.runnable.source)
@@ -82,19 +96,30 @@ impl Device for ScriptDevice {
                     .collect::<Result<Vec<_>>>()?,
             )
             .log_invocation(1)
-            .status()?;
-        if !status.success() {
-            bail!("Test failed")
-        }
+            .spawn()?;
+        // the script is responsible for stopping what it started on the device when it gets
+        // SIGTERM
+        let pid = child.id();
+        let _terminate_on_interrupt = interrupt::on_interrupt(move || terminate_process(pid));
+        let status = child.wait()?;
 
-        Ok(BuildBundle {
+        let bundle = BuildBundle {
             id: build.runnable.id.clone(),
             bundle_dir: bundle_path.to_path_buf(),
             bundle_exe: build.runnable.exe.to_path_buf(),
             lib_dir: build.target_path.clone(),
             root_dir: root_dir.clone(),
             app_id: None,
-        })
+        };
+        Ok(RunOutcome::from_status(bundle, status, started))
+    }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        self.conf.timeout.map(std::time::Duration::from_secs)
+    }
+
+    fn launcher(&self) -> Option<Vec<String>> {
+        self.conf.launcher.clone()
     }
 }
 
@@ -25,6 +25,7 @@ impl PlatformManager for ScriptDeviceManager {
                 Box::new(ScriptDevice {
                     id: k.clone(),
                     conf: conf.clone(),
+                    plugin: None,
                 }) as _
             })
             .collect())
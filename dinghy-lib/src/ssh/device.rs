@@ -1,7 +1,14 @@
-use crate::config::SshDeviceConfiguration;
-use crate::device::make_remote_app;
+use super::manifest::{Manifest, REMOTE_HASH_COMMAND};
+use super::probe::{rustc_triples, PROBE_COMMAND};
+use crate::config::{SshDebugger, SshDeviceConfiguration, SshTransfer};
+use crate::device::{
+    files_to_collect, make_remote_app, remote_find_command, remote_kill_command, shell_launcher,
+    REMOTE_PID_RECORDER,
+};
+use crate::doctor::Check;
 use crate::errors::*;
 use crate::host::HostPlatform;
+use crate::interrupt;
 use crate::platform::regular_platform::RegularPlatform;
 use crate::project::Project;
 use crate::utils::{get_current_verbosity, path_to_str, user_facing_log, LogCommandExt};
@@ -9,21 +16,51 @@ use crate::Build;
 use crate::BuildBundle;
 use crate::Device;
 use crate::DeviceCompatibility;
+use crate::DeviceKind;
+use crate::Platform;
+use crate::RunOutcome;
+use fs_err as fs;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::fmt::Formatter;
 use std::fmt::{Debug, Display};
-use std::io::IsTerminal;
+use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
 use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
 
 #[derive(Clone)]
 pub struct SshDevice {
     pub id: String,
     pub conf: SshDeviceConfiguration,
+    /// Name of the plugin that provided this device, if any
+    pub plugin: Option<String>,
+    detected_rustc_triples: OnceLock<std::result::Result<Vec<String>, String>>,
 }
 
 impl SshDevice {
+    /// The configured platform, or the one the device defines with its `target`.
+    fn platform_id(&self) -> Option<&str> {
+        match &self.conf.platform {
+            Some(platform) => Some(platform),
+            None => self.conf.target.as_ref().map(|_| self.id.as_str()),
+        }
+    }
+
+    pub fn new(id: String, conf: SshDeviceConfiguration, plugin: Option<String>) -> SshDevice {
+        SshDevice {
+            id,
+            conf,
+            plugin,
+            detected_rustc_triples: OnceLock::new(),
+        }
+    }
+
     fn install_app(&self, project: &Project, build: &Build) -> Result<(BuildBundle, BuildBundle)> {
         user_facing_log(
             "Installing",
@@ -53,17 +90,94 @@ impl SshDevice {
     }
 
     fn ssh_command(&self) -> Result<Command> {
+        self.ssh_command_with_options(&[])
+    }
+
+    fn ssh_command_with_options(&self, options: &[&str]) -> Result<Command> {
         let mut command = Command::new("ssh");
-        if let Some(port) = self.conf.port {
-            command.arg("-p").arg(&format!("{}", port));
+        // first, to override the configuration
+        for option in options {
+            command.arg("-o").arg(option);
         }
+        command.args(self.transport_args()?);
         if std::io::stdout().is_terminal() {
             command.arg("-t").arg("-o").arg("LogLevel=QUIET");
         }
-        command.arg(format!("{}@{}", self.conf.username, self.conf.hostname));
+        command.arg(self.destination()?);
         Ok(command)
     }
 
+    /// `[user@]host` to connect to, the host being an alias from the ssh configuration when
+    /// `ssh_config_host` is set.
+    fn destination(&self) -> Result<String> {
+        let host = match &self.conf.ssh_config_host {
+            Some(alias) => alias,
+            None if self.conf.hostname.is_empty() => {
+                bail!(
+                    "Ssh device {} needs a hostname or a ssh_config_host",
+                    self.id
+                )
+            }
+            None => &self.conf.hostname,
+        };
+        if self.conf.username.is_empty() {
+            Ok(host.clone())
+        } else {
+            Ok(format!("{}@{}", self.conf.username, host))
+        }
+    }
+
+    /// Arguments understood by ssh, scp and sftp alike, so that every command reaches the device
+    /// the same way. The `ssh_options` come first, as ssh keeps the first value it gets for an
+    /// option.
+    fn transport_args(&self) -> Result<Vec<String>> {
+        let mut args = vec![];
+        for option in &self.conf.ssh_options {
+            args.push("-o".to_string());
+            args.push(option.clone());
+        }
+        if let Some(port) = self.conf.port {
+            args.push("-o".to_string());
+            args.push(format!("Port={}", port));
+        }
+        if let Some(identity_file) = &self.conf.identity_file {
+            args.push("-i".to_string());
+            args.push(shellexpand::tilde(identity_file).into_owned());
+        }
+        if let Some(proxy_jump) = &self.conf.proxy_jump {
+            args.push("-J".to_string());
+            args.push(proxy_jump.clone());
+        }
+        for option in self.connection_sharing_options()? {
+            args.push("-o".to_string());
+            args.push(option);
+        }
+        Ok(args)
+    }
+
+    /// Options making ssh, scp and rsync go through a master connection to the device, which
+    /// stays up for a minute after its last use. The control socket only depends on the
+    /// destination, so the runners cargo spawns for each executable share it too.
+    fn connection_sharing_options(&self) -> Result<Vec<String>> {
+        if self.conf.connection_sharing == Some(false) {
+            return Ok(vec![]);
+        }
+        let Some(control_dir) = dirs::home_dir().map(|it| it.join(".dinghy").join("ssh")) else {
+            return Ok(vec![]);
+        };
+        if !control_dir.exists() {
+            fs::create_dir_all(&control_dir)?;
+            fs::set_permissions(&control_dir, std::fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(vec![
+            "ControlMaster=auto".to_string(),
+            // %C is a hash of the local host, remote host, port and user, short enough for the
+            // socket path length limit
+            format!("ControlPath={}", control_dir.join("%C").display()),
+            "ControlPersist=60".to_string(),
+        ])
+    }
+
     fn sync_rsync(&self) -> Result<String> {
         match &self.conf.install_adhoc_rsync_local_path {
             Some(rsync) => {
@@ -91,14 +205,9 @@ impl SshDevice {
                         command.arg("-O");
                     }
                     command.arg("-q");
-                    if let Some(port) = self.conf.port {
-                        command.arg("-P").arg(&format!("{}", port));
-                    }
+                    command.args(self.transport_args()?);
                     command.arg(format!("{}", rsync));
-                    command.arg(format!(
-                        "{}@{}:{}",
-                        self.conf.username, self.conf.hostname, rsync_path
-                    ));
+                    command.arg(format!("{}:{}", self.destination()?, rsync_path));
                     log::debug!("Running {:?}", command);
                     if !command.log_invocation(3).status()?.success() {
                         bail!("Error copying rsync binary ({:?})", command)
@@ -111,6 +220,14 @@ impl SshDevice {
     }
 
     fn sync<FP: AsRef<Path>, TP: AsRef<Path>>(&self, from_path: FP, to_path: TP) -> Result<()> {
+        match self.conf.transfer {
+            SshTransfer::Rsync => self.sync_with_rsync(from_path.as_ref(), to_path.as_ref()),
+            transfer => self.sync_changed_files(transfer, from_path.as_ref(), to_path.as_ref()),
+        }
+    }
+
+    /// An rsync command talking to the rsync of the device through ssh.
+    fn rsync_command(&self) -> Result<Command> {
         let rsync = self.sync_rsync();
         let rsync = match rsync {
             Ok(rsync_path) => rsync_path,
@@ -119,20 +236,32 @@ impl SshDevice {
         let mut command = Command::new("rsync");
         command.arg(&format!("--rsync-path={}", rsync));
         command.arg("-a").arg("-v");
-        if let Some(port) = self.conf.port {
-            command.arg("-e").arg(&*format!("ssh -p {}", port));
-        };
+        let transport_args = self.transport_args()?;
+        if !transport_args.is_empty() {
+            let remote_shell = std::iter::once("ssh".into())
+                .chain(
+                    transport_args
+                        .into_iter()
+                        .map(|it| shell_escape::escape(it.into())),
+                )
+                .collect::<Vec<_>>();
+            command.arg("-e").arg(remote_shell.join(" "));
+        }
+        Ok(command)
+    }
+
+    fn sync_with_rsync(&self, from_path: &Path, to_path: &Path) -> Result<()> {
+        let mut command = self.rsync_command()?;
         if !log::log_enabled!(::log::Level::Debug) {
             command.stdout(::std::process::Stdio::null());
             command.stderr(::std::process::Stdio::null());
         }
         command
-            .arg(&format!("{}/", path_to_str(&from_path.as_ref())?))
+            .arg(&format!("{}/", path_to_str(from_path)?))
             .arg(&format!(
-                "{}@{}:{}/",
-                self.conf.username,
-                self.conf.hostname,
-                path_to_str(&to_path.as_ref())?
+                "{}:{}/",
+                self.destination()?,
+                path_to_str(to_path)?
             ));
         log::debug!("Running {:?}", command);
         if !command
@@ -147,59 +276,483 @@ impl SshDevice {
         }
     }
 
+    /// Copies the files of `from_path` which are missing or different in `to_path`, comparing
+    /// their hashes with the ones of the files on the device, which a run may have changed.
+    fn sync_changed_files(
+        &self,
+        transfer: SshTransfer,
+        from_path: &Path,
+        to_path: &Path,
+    ) -> Result<()> {
+        let to = path_to_str(to_path)?;
+        let local = Manifest::of_dir(from_path)?;
+        let remote = self
+            .ssh_command()?
+            .arg(format!(
+                "cd '{}' 2>/dev/null && {}",
+                to, REMOTE_HASH_COMMAND
+            ))
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .log_invocation(2)
+            .output()?;
+        let remote = Manifest::parse(&String::from_utf8_lossy(&remote.stdout));
+        let changed = local.changed_since(&remote);
+        if changed.is_empty() {
+            log::debug!("{} is up to date on {}", to, self.id);
+            return Ok(());
+        }
+        log::debug!(
+            "Copying {} file(s) to {} with {}",
+            changed.len(),
+            to,
+            transfer
+        );
+
+        let mut dirs = changed
+            .iter()
+            .filter_map(|it| it.parent())
+            .map(|it| to_path.join(it))
+            .collect::<Vec<_>>();
+        dirs.sort();
+        dirs.dedup();
+        let copied = match transfer {
+            SshTransfer::Tar => {
+                let mut tar = Command::new("tar")
+                    .arg("-C")
+                    .arg(from_path)
+                    .arg("-cf")
+                    .arg("-")
+                    .args(&changed)
+                    .stdout(Stdio::piped())
+                    .log_invocation(2)
+                    .spawn()?;
+                let extracted = self
+                    .ssh_command()?
+                    .arg(format!("mkdir -p '{}' && tar -C '{}' -xf -", to, to))
+                    .stdin(tar.stdout.take().unwrap())
+                    .log_invocation(1)
+                    .status()?;
+                tar.wait()?.success() && extracted.success()
+            }
+            SshTransfer::Scp => {
+                let created = self
+                    .ssh_command()?
+                    .arg("mkdir")
+                    .arg("-p")
+                    .args(&dirs)
+                    .stdin(Stdio::null())
+                    .log_invocation(2)
+                    .status()?;
+                let mut copied = created.success();
+                for dir in &dirs {
+                    let files = changed
+                        .iter()
+                        .filter(|it| to_path.join(it.parent().unwrap_or(Path::new(""))) == *dir)
+                        .map(|it| from_path.join(it));
+                    let mut command = Command::new("scp");
+                    command.arg("-q").arg("-p");
+                    command.args(self.transport_args()?);
+                    command.args(files).arg(format!(
+                        "{}:{}/",
+                        self.destination()?,
+                        path_to_str(dir)?
+                    ));
+                    copied = copied && command.log_invocation(1).status()?.success();
+                }
+                copied
+            }
+            SshTransfer::Sftp => {
+                let mut batch = String::new();
+                for dir in dirs
+                    .iter()
+                    .flat_map(|it| it.ancestors())
+                    .collect::<BTreeSet<_>>()
+                {
+                    // the leading dash ignores the failure when the directory exists
+                    batch.push_str(&format!("-mkdir \"{}\"\n", path_to_str(dir)?));
+                }
+                for file in &changed {
+                    batch.push_str(&format!(
+                        "put -p \"{}\" \"{}\"\n",
+                        path_to_str(&from_path.join(file))?,
+                        path_to_str(&to_path.join(file))?
+                    ));
+                }
+                let mut command = Command::new("sftp");
+                command.arg("-q").arg("-b").arg("-");
+                command.args(self.transport_args()?);
+                command.arg(self.destination()?);
+                if !log::log_enabled!(::log::Level::Debug) {
+                    command.stdout(Stdio::null());
+                }
+                let mut sftp = command.stdin(Stdio::piped()).log_invocation(1).spawn()?;
+                sftp.stdin.take().unwrap().write_all(batch.as_bytes())?;
+                sftp.wait()?.success()
+            }
+            SshTransfer::Rsync => unreachable!(),
+        };
+        if !copied {
+            bail!(
+                "Error copying {} to {} with {}",
+                from_path.display(),
+                to,
+                transfer
+            )
+        }
+        Ok(())
+    }
+
+    /// The arguments of the executable, with the `remote_shell_vars` expanded, and escaped for
+    /// the remote shell.
+    fn remote_args(&self, args: &[&str]) -> Vec<String> {
+        let remote_shell_vars_as_context = |a: &str| -> Option<std::borrow::Cow<str>> {
+            self.conf.remote_shell_vars.get(a).map(|s| s.into())
+        };
+        args.iter()
+            .map(|&a| {
+                shellexpand::full_with_context_no_errors(
+                    a,
+                    || remote_shell_vars_as_context("HOME"),
+                    remote_shell_vars_as_context,
+                )
+            })
+            .map(|a| ::shell_escape::escape(a).to_string())
+            .collect()
+    }
+
+    /// Connects to the device to find the rustc triples it can run.
+    fn probe_rustc_triples(&self) -> Result<Vec<String>> {
+        let output = self
+            .ssh_command()?
+            .arg(PROBE_COMMAND)
+            .stdin(Stdio::null())
+            .log_invocation(2)
+            .output()?;
+        if !output.status.success() {
+            bail!(
+                "Could not connect to {}: {}",
+                self.id,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        }
+        let probe_output = String::from_utf8_lossy(&output.stdout);
+        let triples = rustc_triples(&probe_output);
+        if triples.is_empty() {
+            bail!(
+                "Unsupported architecture `{}' on {}",
+                probe_output.lines().next().unwrap_or_default().trim(),
+                self.id
+            )
+        }
+        log::debug!("Detected {:?} on {}", triples, self.id);
+        Ok(triples)
+    }
+
+    /// The configured debugger, or the one whose server is installed on the device.
+    fn debugger(&self) -> Result<SshDebugger> {
+        if let Some(debugger) = self.conf.debugger {
+            return Ok(debugger);
+        }
+        let output = self
+            .ssh_command()?
+            .arg("command -v gdbserver || command -v lldb-server")
+            .stdin(Stdio::null())
+            .log_invocation(2)
+            .output()?;
+        let server = String::from_utf8_lossy(&output.stdout);
+        if server.trim_end().ends_with("gdbserver") {
+            Ok(SshDebugger::Gdb)
+        } else if server.trim_end().ends_with("lldb-server") {
+            Ok(SshDebugger::Lldb)
+        } else {
+            bail!(
+                "Neither gdbserver nor lldb-server found on {}, install one of them on the device",
+                self.id
+            )
+        }
+    }
+
+    /// The debugger to run on the host, connected to the debug server listening on `port`.
+    fn host_debugger_command(
+        &self,
+        debugger: SshDebugger,
+        exe: &Path,
+        sysroot: Option<&Path>,
+        port: u16,
+    ) -> Result<Command> {
+        let command = match debugger {
+            SshDebugger::Gdb => {
+                let gdb = if which::which("gdb-multiarch").is_ok() {
+                    "gdb-multiarch"
+                } else {
+                    "gdb"
+                };
+                let mut command = Command::new(gdb);
+                command.arg("-q").arg(exe);
+                if let Some(sysroot) = sysroot {
+                    command
+                        .arg("-ex")
+                        .arg(format!("set sysroot {}", sysroot.display()));
+                }
+                command
+                    .arg("-ex")
+                    .arg(format!("target remote 127.0.0.1:{}", port));
+                command
+            }
+            SshDebugger::Lldb => {
+                let mut command = Command::new("lldb");
+                command.arg(exe);
+                if let Some(sysroot) = sysroot {
+                    command.arg("-o").arg(format!(
+                        "platform select remote-linux --sysroot {}",
+                        sysroot.display()
+                    ));
+                }
+                command
+                    .arg("-o")
+                    .arg(format!("gdb-remote 127.0.0.1:{}", port));
+                command
+            }
+        };
+        Ok(command)
+    }
+
     fn to_remote_bundle(&self, build_bundle: &BuildBundle) -> Result<BuildBundle> {
         let remote_prefix =
             PathBuf::from(self.conf.path.clone().unwrap_or("/tmp".into())).join("dinghy");
         build_bundle.replace_prefix_with(remote_prefix)
     }
+
+    /// The exit code `run_app` recorded for the last run in `bundle_dir`, consumed so that a
+    /// later ssh failure is not mistaken for it.
+    fn recorded_exit_code(&self, bundle_dir: &str) -> Option<i32> {
+        let output = self
+            .ssh_command()
+            .ok()?
+            .arg(format!(
+                "cd '{}' && cat dinghy.exit && rm -f dinghy.exit",
+                bundle_dir
+            ))
+            .stdin(Stdio::null())
+            .log_invocation(2)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+    }
+
+    /// Checks the device is reachable without a password prompt and has rsync.
+    pub(crate) fn diagnose(&self, checks: &mut Vec<Check>) {
+        let subject = format!("ssh device {}", self.id);
+        // never prompt, and do not wait for too long on unreachable hosts
+        let options = ["BatchMode=yes", "ConnectTimeout=5"];
+        let reachable = self
+            .ssh_command_with_options(&options)
+            .and_then(|mut command| {
+                Ok(command
+                    .arg("true")
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .log_invocation(2)
+                    .status()?)
+            });
+        match reachable {
+            Ok(status) if status.success() => checks.push(Check::pass(
+                &subject,
+                format!("{} reachable", self.destination().unwrap_or_default()),
+            )),
+            _ => {
+                checks.push(
+                    Check::fail(
+                        &subject,
+                        format!("could not connect to {}", self.destination().unwrap_or_default()),
+                    )
+                    .with_hint(
+                        "check the device is up and that key based authentication works, with `ssh-copy-id` for instance",
+                    ),
+                );
+                return;
+            }
+        }
+
+        if self.conf.transfer != SshTransfer::Rsync {
+            checks.push(Check::pass(
+                &subject,
+                format!("files are copied with {}", self.conf.transfer),
+            ));
+            return;
+        }
+        if self.conf.install_adhoc_rsync_local_path.is_some() {
+            checks.push(Check::pass(&subject, "rsync will be installed by dinghy"));
+            return;
+        }
+        let has_rsync = self
+            .ssh_command_with_options(&options)
+            .and_then(|mut command| {
+                Ok(command
+                    .arg("test -x /usr/bin/rsync")
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .log_invocation(2)
+                    .status()?)
+            })
+            .map(|status| status.success())
+            .unwrap_or(false);
+        if has_rsync {
+            checks.push(Check::pass(&subject, "remote rsync found"));
+        } else {
+            checks.push(
+                Check::fail(&subject, "/usr/bin/rsync not found on the device").with_hint(
+                    "install rsync on the device, set install_adhoc_rsync_local_path, or set transfer to tar, sftp or scp",
+                ),
+            );
+        }
+    }
 }
 
 impl DeviceCompatibility for SshDevice {
     fn is_compatible_with_regular_platform(&self, platform: &RegularPlatform) -> bool {
-        self.conf
-            .platform
-            .as_ref()
-            .map_or(false, |it| *it == platform.id)
+        if let Ok(Some(triples)) = self.detected_rustc_triples() {
+            return triples.contains(&platform.toolchain.rustc_triple);
+        }
+        self.platform_id().map_or(false, |it| it == platform.id)
     }
 
     fn is_compatible_with_host_platform(&self, platform: &HostPlatform) -> bool {
-        self.conf
-            .platform
-            .as_ref()
-            .map_or(true, |it| *it == platform.id)
+        if self.platform_id().is_none() && self.conf.detect_platform {
+            return self
+                .detected_rustc_triples()
+                .ok()
+                .flatten()
+                .is_some_and(|triples| triples.iter().any(|it| it == platform.rustc_triple()));
+        }
+        self.platform_id().map_or(true, |it| it == platform.id)
     }
 }
 
 impl Device for SshDevice {
     fn clean_app(&self, build_bundle: &BuildBundle) -> Result<()> {
+        let remote_bundle = self.to_remote_bundle(build_bundle)?;
         let status = self
             .ssh_command()?
             .arg(&format!(
-                "rm -rf {}",
-                path_to_str(&build_bundle.bundle_exe)?
+                "rm -rf {} {}",
+                path_to_str(&remote_bundle.bundle_dir)?,
+                path_to_str(&remote_bundle.lib_dir)?
             ))
             .log_invocation(1)
             .status()?;
         if !status.success() {
-            bail!("test fail.")
+            bail!("Failed to cleanup {}", self.id)
         }
         Ok(())
     }
 
     fn debug_app(
         &self,
-        _project: &Project,
-        _build: &Build,
-        _args: &[&str],
-        _envs: &[&str],
+        project: &Project,
+        build: &Build,
+        args: &[&str],
+        envs: &[&str],
     ) -> Result<BuildBundle> {
-        unimplemented!()
+        let args = self.remote_args(args);
+        let (build_bundle, remote_bundle) = self.install_app(project, build)?;
+        let debugger = self.debugger()?;
+        // a free local port, also used on the device where it is most likely free too
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let server = match debugger {
+            SshDebugger::Gdb => format!("gdbserver 127.0.0.1:{}", port),
+            SshDebugger::Lldb => format!("lldb-server gdbserver 127.0.0.1:{} --", port),
+        };
+        let command = format!(
+            "cd '{}' ; {} DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {} {} 2>&1",
+            path_to_str(&remote_bundle.bundle_dir)?,
+            envs.join(" "),
+            path_to_str(&remote_bundle.lib_dir)?,
+            REMOTE_PID_RECORDER,
+            server,
+            path_to_str(&remote_bundle.bundle_exe)?,
+            args.join(" ")
+        );
+        user_facing_log(
+            "Debugging",
+            &format!("{} on {}", build.runnable.id, self.id),
+            0,
+        );
+
+        // a dedicated connection for the port forward, in its own process group so that the
+        // Ctrl-C meant for the debugger does not end it
+        let forward = format!("LocalForward=127.0.0.1:{} 127.0.0.1:{}", port, port);
+        let mut server = self
+            .ssh_command_with_options(&["ControlPath=none", "ExitOnForwardFailure=yes", &forward])?
+            .arg(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .log_invocation(1)
+            .spawn()?;
+        let mut lines = BufReader::new(server.stdout.take().unwrap()).lines();
+        loop {
+            match lines.next() {
+                Some(Ok(line)) => {
+                    println!("{}", line);
+                    if line.contains("Listening") {
+                        break;
+                    }
+                }
+                _ => {
+                    let _ = server.wait();
+                    bail!("Could not start the debug server on {}", self.id)
+                }
+            }
+        }
+        // the output of the program being debugged
+        std::thread::spawn(move || {
+            for line in lines.map_while(|it| it.ok()) {
+                println!("{}", line);
+            }
+        });
+
+        let status = self
+            .host_debugger_command(
+                debugger,
+                &build.runnable.exe,
+                build.sysroot.as_deref(),
+                port,
+            )?
+            .log_invocation(1)
+            .status()
+            .context("Could not start the debugger on the host");
+
+        let _ = self
+            .ssh_command()?
+            .arg(remote_kill_command(path_to_str(&remote_bundle.bundle_dir)?))
+            .stdin(Stdio::null())
+            .log_invocation(2)
+            .status();
+        let _ = server.kill();
+        let _ = server.wait();
+        if !status?.success() {
+            bail!("The debugger failed")
+        }
+        Ok(build_bundle)
     }
 
     fn id(&self) -> &str {
         &self.id
     }
 
+    fn kind(&self) -> DeviceKind {
+        if self.plugin.is_some() {
+            DeviceKind::Plugin
+        } else {
+            DeviceKind::Ssh
+        }
+    }
+
     fn name(&self) -> &str {
         &self.id
     }
@@ -210,29 +763,21 @@ impl Device for SshDevice {
         build: &Build,
         args: &[&str],
         envs: &[&str],
-    ) -> Result<BuildBundle> {
-        let remote_shell_vars_as_context = |a: &str| -> Option<std::borrow::Cow<str>> {
-            self.conf.remote_shell_vars.get(a).map(|s| s.into())
-        };
-        let args: Vec<String> = args
-            .iter()
-            .map(|&a| {
-                shellexpand::full_with_context_no_errors(
-                    a,
-                    || remote_shell_vars_as_context("HOME"),
-                    remote_shell_vars_as_context,
-                )
-            })
-            .map(|a| ::shell_escape::escape(a).to_string())
-            .collect();
+    ) -> Result<RunOutcome> {
+        let args = self.remote_args(args);
         log::info!("Install {:?}", build.runnable.id);
         let (build_bundle, remote_bundle) = self.install_app(&project, &build)?;
         log::debug!("Installed {:?}", build.runnable.id);
+        // the trailing exit keeps the shell from exec-ing the program, so that it reports the
+        // signal terminating the program as 128 + signal. The exit code is also recorded in
+        // dinghy.exit, to tell a program exiting with 255 from ssh failing.
         let command = format!(
-            "cd '{}' ; RUST_BACKTRACE=1 {} DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {}",
+            "cd '{}' ; rm -f dinghy.exit ; RUST_BACKTRACE=1 {} DINGHY=1 LD_LIBRARY_PATH=\"{}:$LD_LIBRARY_PATH\" {} {} {} {} ; code=$? ; echo $code > dinghy.exit ; exit $code",
             path_to_str(&remote_bundle.bundle_dir)?,
             envs.join(" "),
             path_to_str(&remote_bundle.lib_dir)?,
+            REMOTE_PID_RECORDER,
+            shell_launcher(build),
             path_to_str(&remote_bundle.bundle_exe)?,
             args.join(" ")
         );
@@ -248,16 +793,146 @@ impl Device for SshDevice {
             );
         }
 
+        let kill_command = remote_kill_command(path_to_str(&remote_bundle.bundle_dir)?);
+        let device = self.clone();
+        let _kill_on_interrupt = interrupt::on_interrupt(move || {
+            log::debug!("Killing the remote program on {}", device.id);
+            let _ = device.ssh_command().and_then(|mut command| {
+                Ok(command.arg(&kill_command).log_invocation(1).status()?)
+            });
+        });
+        let started = Instant::now();
         let status = self
             .ssh_command()?
             .arg(&command)
             .log_invocation(1)
             .status()?;
-        if !status.success() {
-            bail!("Failed")
+        match status.code() {
+            Some(255)
+                if self.recorded_exit_code(path_to_str(&remote_bundle.bundle_dir)?)
+                    != Some(255) =>
+            {
+                bail!("Could not run {} on {}", build.runnable.id, self.id)
+            }
+            Some(code) => Ok(RunOutcome::from_shell_exit_code(
+                build_bundle,
+                code,
+                started,
+            )),
+            None => Ok(RunOutcome::from_status(build_bundle, status, started)),
         }
+    }
 
-        Ok(build_bundle)
+    fn collect_files(
+        &self,
+        build_bundle: &BuildBundle,
+        patterns: &[String],
+        destination: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        let remote_bundle = self.to_remote_bundle(build_bundle)?;
+        let bundle_dir = path_to_str(&remote_bundle.bundle_dir)?;
+        let listing = self
+            .ssh_command()?
+            .arg(remote_find_command(bundle_dir))
+            .stdin(Stdio::null())
+            .log_invocation(2)
+            .output()?;
+        if !listing.status.success() {
+            bail!("Could not list the files of {} on {}", bundle_dir, self.id)
+        }
+        let files = files_to_collect(&String::from_utf8_lossy(&listing.stdout), patterns)?;
+        if files.is_empty() {
+            return Ok(files);
+        }
+        fs::create_dir_all(destination)?;
+        let copied = match self.conf.transfer {
+            SshTransfer::Rsync => {
+                let mut rsync = self
+                    .rsync_command()?
+                    .arg("--files-from=-")
+                    .arg(format!("{}:{}/", self.destination()?, bundle_dir))
+                    .arg(destination)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .log_invocation(1)
+                    .spawn()?;
+                let mut file_list = rsync.stdin.take().unwrap();
+                for file in &files {
+                    writeln!(file_list, "{}", path_to_str(file)?)?;
+                }
+                drop(file_list);
+                rsync.wait()?.success()
+            }
+            // scp is the most likely to be available for the other transfers
+            _ => {
+                let mut dirs = files
+                    .iter()
+                    .filter_map(|it| it.parent())
+                    .collect::<Vec<_>>();
+                dirs.sort();
+                dirs.dedup();
+                let mut copied = true;
+                for dir in dirs {
+                    fs::create_dir_all(destination.join(dir))?;
+                    let mut command = Command::new("scp");
+                    command.arg("-q").arg("-p");
+                    command.args(self.transport_args()?);
+                    for file in files.iter().filter(|it| it.parent() == Some(dir)) {
+                        command.arg(format!(
+                            "{}:{}/{}",
+                            self.destination()?,
+                            bundle_dir,
+                            path_to_str(file)?
+                        ));
+                    }
+                    command.arg(destination.join(dir));
+                    copied = copied && command.log_invocation(1).status()?.success();
+                }
+                copied
+            }
+        };
+        if !copied {
+            bail!("Error collecting files from {} on {}", bundle_dir, self.id)
+        }
+        Ok(files)
+    }
+
+    fn detected_rustc_triples(&self) -> Result<Option<Vec<String>>> {
+        if self.platform_id().is_some() || !self.conf.detect_platform {
+            return Ok(None);
+        }
+        self.detected_rustc_triples
+            .get_or_init(|| self.probe_rustc_triples().map_err(|e| e.to_string()))
+            .clone()
+            .map(Some)
+            .map_err(|e| anyhow!(e))
+    }
+
+    /// The platform whose triple comes first among the detected ones, the best match.
+    fn preferred_platform(
+        &self,
+        platforms: &[Arc<Box<dyn Platform>>],
+    ) -> Option<Arc<Box<dyn Platform>>> {
+        let Ok(Some(triples)) = self.detected_rustc_triples() else {
+            return platforms.first().cloned();
+        };
+        platforms
+            .iter()
+            .min_by_key(|pf| {
+                triples
+                    .iter()
+                    .position(|it| it == pf.rustc_triple())
+                    .unwrap_or(usize::MAX)
+            })
+            .cloned()
+    }
+
+    fn timeout(&self) -> Option<std::time::Duration> {
+        self.conf.timeout.map(std::time::Duration::from_secs)
+    }
+
+    fn launcher(&self) -> Option<Vec<String>> {
+        self.conf.launcher.clone()
     }
 }
 
@@ -273,6 +948,62 @@ impl Debug for SshDevice {
 
 impl Display for SshDevice {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
-        write!(fmt, "{} ({})", self.id, self.conf.hostname)
+        let host = self
+            .conf
+            .ssh_config_host
+            .as_ref()
+            .unwrap_or(&self.conf.hostname);
+        write!(fmt, "{} ({})", self.id, host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PlatformConfiguration;
+    use crate::toolchain::ToolchainConfig;
+
+    #[allow(clippy::arc_with_non_send_sync)]
+    fn platform(id: &str, rustc_triple: &str) -> Arc<Box<dyn Platform>> {
+        let toolchain = ToolchainConfig {
+            bin_dir: PathBuf::from("/tc/bin"),
+            root: PathBuf::from("/tc"),
+            rustc_triple: rustc_triple.to_string(),
+            sysroot: None,
+            cc: "gcc".to_string(),
+            cxx: "g++".to_string(),
+            binutils_prefix: rustc_triple.to_string(),
+            cc_prefix: rustc_triple.to_string(),
+        };
+        Arc::new(
+            RegularPlatform::new_with_tc(PlatformConfiguration::empty(), id.to_string(), toolchain)
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_preferred_platform_follows_detected_triples() {
+        let conf = toml::from_str("hostname = \"board\"\ndetect_platform = true").unwrap();
+        let device = SshDevice::new("board".to_string(), conf, None);
+        let probe_output = "armv7l\n/lib/ld-linux-armhf.so.3\n";
+        device
+            .detected_rustc_triples
+            .set(Ok(crate::ssh::probe::rustc_triples(probe_output)))
+            .unwrap();
+        // in config order, the armv6 platform comes before the armv7 one
+        let platforms = vec![
+            platform("arm-soft", "arm-unknown-linux-gnueabi"),
+            platform("arm-hf", "arm-unknown-linux-gnueabihf"),
+            platform("armv7-hf", "armv7-unknown-linux-gnueabihf"),
+        ];
+        let compatible = platforms
+            .into_iter()
+            .filter(|it| it.is_compatible_with(&device))
+            .collect::<Vec<_>>();
+        assert_eq!(compatible.len(), 2);
+        assert_eq!(
+            device.preferred_platform(&compatible).unwrap().id(),
+            "armv7-hf"
+        );
     }
 }
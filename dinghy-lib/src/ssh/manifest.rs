@@ -0,0 +1,84 @@
+use crate::errors::*;
+use fs_err as fs;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Shell command printing the hashes of the files of the current directory on a device, in the
+/// format of [`Manifest`]. Devices without `sha256sum` get every file copied.
+pub(crate) const REMOTE_HASH_COMMAND: &str =
+    "find . -type f -exec sha256sum {} + 2>/dev/null ; true";
+
+/// Sha256 of the files of a directory, by path relative to it, one `<hash>  <path>` per line.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Manifest(BTreeMap<PathBuf, String>);
+
+impl Manifest {
+    pub(crate) fn of_dir(dir: &Path) -> Result<Manifest> {
+        let mut files = BTreeMap::new();
+        if !dir.is_dir() {
+            return Ok(Manifest(files));
+        }
+        for entry in WalkDir::new(dir).follow_links(true) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let hash = format!("{:x}", Sha256::digest(fs::read(entry.path())?));
+            files.insert(entry.path().strip_prefix(dir)?.to_path_buf(), hash);
+        }
+        Ok(Manifest(files))
+    }
+
+    /// Reads a manifest back from `sha256sum` output, ignoring malformed lines.
+    pub(crate) fn parse(content: &str) -> Manifest {
+        Manifest(
+            content
+                .lines()
+                .filter_map(|line| line.split_once("  "))
+                .map(|(hash, path)| {
+                    let path = path.strip_prefix("./").unwrap_or(path);
+                    (PathBuf::from(path), hash.to_string())
+                })
+                .collect(),
+        )
+    }
+
+    /// Files which are missing or different in `previous`.
+    pub(crate) fn changed_since(&self, previous: &Manifest) -> Vec<&Path> {
+        self.0
+            .iter()
+            .filter(|(path, hash)| previous.0.get(*path) != Some(*hash))
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (path, hash) in &self.0 {
+            writeln!(f, "{}  {}", hash, path.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use std::path::Path;
+
+    #[test]
+    fn test_changed_since() {
+        let previous = Manifest::parse("aa  ./bin/test\nbb  ./data/a file.txt\nbroken line\n");
+        let current = Manifest::parse("aa  bin/test\ncc  data/a file.txt\ndd  data/new\n");
+        assert_eq!(
+            current.changed_since(&previous),
+            vec![Path::new("data/a file.txt"), Path::new("data/new")]
+        );
+        assert_eq!(Manifest::parse(&current.to_string()), current);
+        assert!(current.changed_since(&current).is_empty());
+    }
+}
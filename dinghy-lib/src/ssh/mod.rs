@@ -1,5 +1,9 @@
 mod device;
-use crate::{Configuration, Device, Platform, PlatformManager, Result};
+mod manifest;
+mod probe;
+use crate::errors::*;
+use crate::platform::regular_platform::RegularPlatform;
+use crate::{Configuration, Device, Platform, PlatformManager};
 use std::sync;
 
 pub use self::device::SshDevice;
@@ -20,15 +24,33 @@ impl PlatformManager for SshDeviceManager {
             .conf
             .ssh_devices
             .iter()
-            .map(|(k, conf)| {
-                Box::new(SshDevice {
-                    id: k.clone(),
-                    conf: conf.clone(),
-                }) as _
-            })
+            .map(|(k, conf)| Box::new(SshDevice::new(k.clone(), conf.clone(), None)) as _)
             .collect())
     }
     fn platforms(&self) -> Result<Vec<Box<dyn Platform>>> {
-        Ok(vec![])
+        let mut platforms = vec![];
+        for (id, conf) in &self.conf.ssh_devices {
+            if conf.platform.is_some() && conf.target.is_some() {
+                log::warn!("Ssh device {} has a platform, its target is ignored", id);
+            }
+            let Some(platform_conf) = conf.implicit_platform() else {
+                continue;
+            };
+            if self.conf.platforms.contains_key(id) {
+                bail!(
+                    "Ssh device {} defines a platform with its target, but a {} platform is already configured",
+                    id,
+                    id
+                )
+            }
+            let toolchain = platform_conf
+                .toolchain_path(id)
+                .with_context(|| format!("Toolchain missing for ssh device {}", id))?;
+            let rustc_triple = platform_conf.rustc_triple.clone().unwrap();
+            let platform = RegularPlatform::new(platform_conf, id.clone(), rustc_triple, toolchain)
+                .with_context(|| format!("Could not assemble the platform of ssh device {}", id))?;
+            platforms.push(platform);
+        }
+        Ok(platforms)
     }
 }
@@ -0,0 +1,89 @@
+/// Shell command printing what [`rustc_triples`] needs: the machine name, then the dynamic
+/// loaders of the device, one per line.
+pub(crate) const PROBE_COMMAND: &str =
+    "uname -m ; ls -1 /lib/ld-* /lib64/ld-* /lib/*/ld-* 2>/dev/null ; true";
+
+/// Rustc triples able to run on a device, best match first, from the output of
+/// [`PROBE_COMMAND`].
+///
+/// The libc flavour and the arm float ABI come from the name of the dynamic loader. When there
+/// is none (statically linked systems), both glibc and musl triples are candidates.
+pub(crate) fn rustc_triples(probe_output: &str) -> Vec<String> {
+    let mut lines = probe_output.lines().map(|it| it.trim());
+    let Some(machine) = lines.next() else {
+        return vec![];
+    };
+    let loaders: Vec<&str> = lines
+        .filter_map(|it| it.rsplit('/').next())
+        .filter(|it| it.starts_with("ld-"))
+        .collect();
+    let gnu = loaders.iter().any(|it| it.starts_with("ld-linux"));
+    let musl = loaders.iter().any(|it| it.starts_with("ld-musl"));
+    let hard_float = loaders.iter().any(|it| it.contains("armhf"));
+    let mut envs = vec![];
+    if gnu || !musl {
+        envs.push("gnu");
+    }
+    if musl || !gnu {
+        envs.push("musl");
+    }
+    let with_abi = |envs: &[&str], abi: &str| -> Vec<String> {
+        envs.iter().map(|env| format!("{}{}", env, abi)).collect()
+    };
+    let (archs, envs): (&[&str], Vec<String>) = match machine {
+        "x86_64" | "amd64" => (&["x86_64"], with_abi(&envs, "")),
+        "i386" | "i486" | "i586" => (&["i586"], with_abi(&envs, "")),
+        "i686" => (&["i686", "i586"], with_abi(&envs, "")),
+        "aarch64" | "arm64" => (&["aarch64"], with_abi(&envs, "")),
+        "riscv64" => (&["riscv64gc"], with_abi(&envs, "")),
+        "ppc64le" => (&["powerpc64le"], with_abi(&envs, "")),
+        "s390x" => (&["s390x"], with_abi(&envs, "")),
+        "armv7l" | "armv8l" if hard_float => (&["armv7", "arm"], with_abi(&envs, "eabihf")),
+        "armv7l" | "armv8l" => (&["armv7", "arm"], with_abi(&envs, "eabi")),
+        "armv6l" if hard_float => (&["arm"], with_abi(&envs, "eabihf")),
+        "armv6l" => (&["arm"], with_abi(&envs, "eabi")),
+        "armv5tel" | "armv5tejl" => (&["armv5te"], with_abi(&envs, "eabi")),
+        _ => return vec![],
+    };
+    archs
+        .iter()
+        .flat_map(|arch| {
+            envs.iter()
+                .map(move |env| format!("{}-unknown-linux-{}", arch, env))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rustc_triples;
+
+    #[test]
+    fn test_rustc_triples() {
+        assert_eq!(
+            rustc_triples("aarch64\r\n/lib/ld-linux-aarch64.so.1\r\n"),
+            vec!["aarch64-unknown-linux-gnu"]
+        );
+        assert_eq!(
+            rustc_triples(
+                "armv7l\n/lib/ld-linux-armhf.so.3\n/lib/arm-linux-gnueabihf/ld-linux-armhf.so.3\n"
+            ),
+            vec![
+                "armv7-unknown-linux-gnueabihf",
+                "arm-unknown-linux-gnueabihf"
+            ]
+        );
+        assert_eq!(
+            rustc_triples("x86_64\n/lib/ld-musl-x86_64.so.1\n"),
+            vec!["x86_64-unknown-linux-musl"]
+        );
+        assert_eq!(
+            rustc_triples("riscv64\n"),
+            vec![
+                "riscv64gc-unknown-linux-gnu",
+                "riscv64gc-unknown-linux-musl"
+            ]
+        );
+        assert!(rustc_triples("sparc64\n/lib/ld-linux.so.2\n").is_empty());
+    }
+}
@@ -182,3 +182,42 @@ impl LogCommandExt for Command {
         self
     }
 }
+
+/// Asks a local process to terminate with SIGTERM.
+pub(crate) fn terminate_process(pid: u32) {
+    let _ = Command::new("kill")
+        .arg("-TERM")
+        .arg(pid.to_string())
+        .log_invocation(2)
+        .status();
+}
+
+/// Sends SIGINT to a local process, as a Ctrl-C would, so that a dinghy runner gets to clean up
+/// its device.
+pub fn interrupt_process(pid: u32) {
+    let _ = Command::new("kill")
+        .arg("-INT")
+        .arg(pid.to_string())
+        .log_invocation(2)
+        .status();
+}
+
+/// Name of the common signals, numbered as on linux.
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return None,
+    })
+}